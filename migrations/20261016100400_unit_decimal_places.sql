@@ -0,0 +1,2 @@
+-- The number of decimal places quantities in a unit are rounded to, NULL for unrounded
+ALTER TABLE units ADD COLUMN decimal_places SMALLINT CHECK (decimal_places BETWEEN 0 AND 6);
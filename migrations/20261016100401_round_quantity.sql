@@ -0,0 +1,13 @@
+-- Rounds a quantity to the decimal places of its unit, leaving it as is if the unit has none
+CREATE FUNCTION round_quantity(quantity real, decimal_places smallint) RETURNS real
+LANGUAGE sql IMMUTABLE
+AS $$
+SELECT COALESCE(round(quantity::numeric, decimal_places)::real, quantity)
+$$;
+
+CREATE FUNCTION round_quantity(quantity double precision, decimal_places smallint)
+RETURNS double precision
+LANGUAGE sql IMMUTABLE
+AS $$
+SELECT COALESCE(round(quantity::numeric, decimal_places)::float8, quantity)
+$$;
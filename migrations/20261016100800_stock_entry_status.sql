@@ -0,0 +1,3 @@
+-- Pending entries are recorded but not yet applied to stock
+CREATE TYPE entry_status AS ENUM ('pending', 'confirmed');
+ALTER TABLE stock_entries ADD COLUMN status entry_status NOT NULL DEFAULT 'confirmed';
@@ -0,0 +1,6 @@
+-- The price per stock unit of each product's most recent confirmed, priced purchase
+CREATE VIEW latest_purchase_prices AS
+SELECT DISTINCT ON (product_id) product_id, price
+FROM stock_entries
+WHERE entry_type = 'purchase' AND status = 'confirmed' AND price IS NOT NULL
+ORDER BY product_id, entry_timestamp DESC;
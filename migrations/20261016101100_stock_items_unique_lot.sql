@@ -0,0 +1,33 @@
+-- `UNIQUE NULLS NOT DISTINCT` needs PostgreSQL 15 or later
+DO $$
+BEGIN
+    IF current_setting('server_version_num')::integer < 150000 THEN
+        RAISE EXCEPTION 'UKIS requires PostgreSQL 15 or later, found %', current_setting('server_version');
+    END IF;
+END
+$$;
+-- Merge the stock items of the same lot into the one with the lowest id, moving their entries to it
+UPDATE stock_entries e
+SET stock_item_id = lots.kept_id
+FROM (
+    SELECT id, MIN(id) OVER (PARTITION BY product_id, space_id, best_by_date) AS kept_id
+    FROM stock_items
+) lots
+WHERE e.stock_item_id = lots.id AND lots.id <> lots.kept_id;
+UPDATE stock_items si
+SET stock_quantity = lots.total_quantity
+FROM (
+    SELECT MIN(id) AS kept_id, SUM(stock_quantity) AS total_quantity
+    FROM stock_items
+    GROUP BY product_id, space_id, best_by_date
+    HAVING COUNT(*) > 1
+) lots
+WHERE si.id = lots.kept_id;
+DELETE FROM stock_items si
+USING stock_items kept
+WHERE kept.product_id = si.product_id AND kept.space_id = si.space_id
+    AND kept.best_by_date IS NOT DISTINCT FROM si.best_by_date AND kept.id < si.id;
+-- A product has at most one stock item per space and best by date
+ALTER TABLE stock_items
+ADD CONSTRAINT stock_items_product_space_best_by_key
+UNIQUE NULLS NOT DISTINCT (product_id, space_id, best_by_date);
@@ -0,0 +1,12 @@
+-- Cached per-product stock totals, refreshed after stock changes
+CREATE MATERIALIZED VIEW product_stock_totals AS
+SELECT p.id AS product_id,
+    COALESCE(SUM(si.stock_quantity), 0)::float8 AS total_quantity,
+    COUNT(si.id) AS stock_item_count,
+    LOCALTIMESTAMP AS refreshed_at
+FROM products p
+LEFT JOIN stock_items si ON si.product_id = p.id
+GROUP BY p.id;
+
+-- Required to refresh the view concurrently
+CREATE UNIQUE INDEX product_stock_totals_product_id_idx ON product_stock_totals (product_id);
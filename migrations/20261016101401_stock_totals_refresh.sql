@@ -0,0 +1,16 @@
+-- When `product_stock_totals` was last refreshed, kept in a single row
+CREATE TABLE stock_totals_refresh (
+    only_row BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (only_row),
+    refreshed_at TIMESTAMP NOT NULL
+);
+INSERT INTO stock_totals_refresh (refreshed_at) VALUES (LOCALTIMESTAMP);
+-- The refresh time moved to `stock_totals_refresh`
+DROP MATERIALIZED VIEW product_stock_totals;
+CREATE MATERIALIZED VIEW product_stock_totals AS
+SELECT p.id AS product_id,
+    COALESCE(SUM(si.stock_quantity), 0)::float8 AS total_quantity,
+    COUNT(si.id) AS stock_item_count
+FROM products p
+LEFT JOIN stock_items si ON si.product_id = p.id
+GROUP BY p.id;
+CREATE UNIQUE INDEX product_stock_totals_product_id_idx ON product_stock_totals (product_id);
@@ -0,0 +1,2 @@
+-- Units are identified by their (case-insensitive) singular form
+CREATE UNIQUE INDEX units_singular_key ON units (lower(singular));
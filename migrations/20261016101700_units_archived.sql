@@ -0,0 +1,2 @@
+-- Units still referenced when deleted are archived instead
+ALTER TABLE units ADD COLUMN archived BOOLEAN NOT NULL DEFAULT false;
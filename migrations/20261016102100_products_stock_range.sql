@@ -0,0 +1,5 @@
+-- The range of total stock a product should be kept within
+ALTER TABLE products
+ADD COLUMN min_stock REAL CHECK (min_stock >= 0),
+ADD COLUMN max_stock REAL CHECK (max_stock >= 0),
+ADD CONSTRAINT products_stock_range_check CHECK (min_stock <= max_stock);
@@ -0,0 +1,2 @@
+-- Adjustments correct stock directly, with a signed quantity
+ALTER TYPE entry_type ADD VALUE 'adjust';
@@ -0,0 +1,2 @@
+-- Deleted conversions are kept, so they can be restored
+ALTER TABLE unit_conversions ADD COLUMN deleted_at TIMESTAMP;
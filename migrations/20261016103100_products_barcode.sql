@@ -0,0 +1,2 @@
+-- Barcodes identify products when scanning and importing them
+ALTER TABLE products ADD COLUMN barcode TEXT UNIQUE;
@@ -0,0 +1,11 @@
+-- The total stock per product (and optionally per space) at points in time, for trends
+CREATE TABLE inventory_snapshots (
+    id SERIAL PRIMARY KEY,
+    taken_at TIMESTAMP NOT NULL,
+    product_id INTEGER NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    -- NULL for the product's total across all spaces
+    space_id INTEGER REFERENCES spaces(id) ON DELETE CASCADE,
+    total_quantity DOUBLE PRECISION NOT NULL
+);
+
+CREATE INDEX inventory_snapshots_taken_at_idx ON inventory_snapshots (taken_at);
@@ -0,0 +1,2 @@
+-- Products can be archived to hide them from listings while keeping their history
+ALTER TABLE products ADD COLUMN archived BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,3 @@
+-- Which lots of a product are used up first: soonest best by date, or first received
+CREATE TYPE depletion_strategy AS ENUM ('fefo', 'fifo');
+ALTER TABLE products ADD COLUMN depletion_strategy depletion_strategy NOT NULL DEFAULT 'fefo';
@@ -0,0 +1,10 @@
+-- When each stock item was created, backfilled from its earliest entry where there is one
+ALTER TABLE stock_items ADD COLUMN created_at TIMESTAMP;
+UPDATE stock_items si
+SET created_at = COALESCE(
+    (SELECT MIN(e.entry_timestamp) FROM stock_entries e WHERE e.stock_item_id = si.id),
+    LOCALTIMESTAMP
+);
+ALTER TABLE stock_items
+    ALTER COLUMN created_at SET DEFAULT LOCALTIMESTAMP,
+    ALTER COLUMN created_at SET NOT NULL;
@@ -0,0 +1,5 @@
+-- Alternative names units can be looked up by, trimmed and lowercased
+CREATE TABLE unit_aliases (
+    alias TEXT PRIMARY KEY,
+    unit_id INTEGER NOT NULL REFERENCES units(id) ON DELETE CASCADE
+);
@@ -0,0 +1,7 @@
+-- Formats a quantity with its unit for display (e.g. '2.5 kilograms'), using the singular form
+-- for exactly 1 and the plural form, if any, otherwise
+CREATE FUNCTION format_quantity(quantity real, singular text, plural text) RETURNS text
+LANGUAGE sql IMMUTABLE
+AS $$
+SELECT quantity::text || ' ' || CASE WHEN quantity = 1 THEN singular ELSE COALESCE(plural, singular) END
+$$;
@@ -0,0 +1,8 @@
+-- Stock items deleted on purpose, which their stock entries keep referencing
+CREATE TABLE deleted_stock_items (
+    id INTEGER PRIMARY KEY,
+    product_id INTEGER NOT NULL,
+    space_id INTEGER NOT NULL,
+    best_by_date DATE,
+    deleted_at TIMESTAMP NOT NULL DEFAULT LOCALTIMESTAMP
+);
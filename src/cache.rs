@@ -0,0 +1,64 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+
+/// An in-memory cache of a report's results per set of parameters, which expire after a fixed
+/// time
+pub struct ReportCache<T> {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CachedReport<T>>>,
+}
+
+struct CachedReport<T> {
+    value: T,
+    cached_at: DateTime<Utc>,
+    expires_at: Instant,
+}
+
+impl<T: Clone> ReportCache<T> {
+    /// Creates an empty cache keeping results for `ttl`, which doesn't keep any if it's zero
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The result cached for `key` and when it was cached, unless it has expired
+    pub fn get(&self, key: &str) -> Option<(T, DateTime<Utc>)> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| (entry.value.clone(), entry.cached_at))
+    }
+
+    /// Caches `value` as the result for `key`, returning when it was cached
+    ///
+    /// Expired results are dropped along the way, so parameters that aren't requested again
+    /// don't pile up.
+    pub fn insert(&self, key: String, value: T) -> DateTime<Utc> {
+        let cached_at = Utc::now();
+        if self.ttl.is_zero() {
+            return cached_at;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.expires_at > now);
+        entries.insert(
+            key,
+            CachedReport {
+                value,
+                cached_at,
+                expires_at: now + self.ttl,
+            },
+        );
+
+        cached_at
+    }
+}
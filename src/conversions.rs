@@ -0,0 +1,254 @@
+use std::collections::{HashMap, VecDeque};
+
+use sqlx::PgExecutor;
+
+/// The relative difference up to which two factors for the same conversion are considered equal
+pub const DEFAULT_FACTOR_TOLERANCE: f64 = 1e-3;
+
+/// The unit conversions as a graph, for converting between units without a direct conversion
+///
+/// Every conversion can be followed in both directions, the reverse using the reciprocal factor.
+pub struct ConversionGraph {
+    edges: HashMap<i32, Vec<(i32, f64)>>,
+    max_path_length: usize,
+}
+
+/// Why two units can't be converted between
+pub enum ConversionError {
+    /// No chain of conversions connects the units
+    NoPath,
+    /// The fewest conversions connecting the units are more than the graph's maximum path length
+    PathTooLong { length: usize },
+}
+
+impl ConversionGraph {
+    /// Loads all unit conversions that have a (non-zero) factor and haven't been deleted, only
+    /// allowing conversions through at most `max_path_length` of them
+    pub async fn load(executor: impl PgExecutor<'_>, max_path_length: usize) -> sqlx::Result<Self> {
+        let conversions = sqlx::query!(
+            r#"
+SELECT from_unit_id, to_unit_id, factor AS "factor!"
+FROM unit_conversions
+WHERE factor IS NOT NULL AND factor <> 0 AND deleted_at IS NULL"#
+        )
+        .fetch_all(executor)
+        .await?;
+
+        Ok(Self::new(
+            conversions.into_iter().map(|conversion| {
+                (
+                    conversion.from_unit_id,
+                    conversion.to_unit_id,
+                    f64::from(conversion.factor),
+                )
+            }),
+            max_path_length,
+        ))
+    }
+
+    /// Builds the graph from `(from_unit_id, to_unit_id, factor)` conversions with non-zero
+    /// factors
+    fn new(conversions: impl IntoIterator<Item = (i32, i32, f64)>, max_path_length: usize) -> Self {
+        let mut edges: HashMap<i32, Vec<(i32, f64)>> = HashMap::new();
+        for (from, to, factor) in conversions {
+            edges.entry(from).or_default().push((to, factor));
+            edges.entry(to).or_default().push((from, 1.0 / factor));
+        }
+
+        Self {
+            edges,
+            max_path_length,
+        }
+    }
+
+    /// The factor converting a quantity in unit `from` to unit `to`, following the path with the
+    /// fewest conversions
+    pub fn factor(&self, from: i32, to: i32) -> Result<f64, ConversionError> {
+        let (factor, length) = self
+            .shortest_path(from, to)
+            .ok_or(ConversionError::NoPath)?;
+        if length > self.max_path_length {
+            tracing::warn!(
+                from,
+                to,
+                length,
+                max_path_length = self.max_path_length,
+                "conversion path exceeds the maximum length"
+            );
+            return Err(ConversionError::PathTooLong { length });
+        }
+        if length + 1 >= self.max_path_length {
+            tracing::info!(
+                from,
+                to,
+                length,
+                max_path_length = self.max_path_length,
+                "conversion path is close to the maximum length"
+            );
+        }
+
+        Ok(factor)
+    }
+
+    /// The factor the existing conversions imply from unit `from` to unit `to`, regardless of
+    /// the maximum path length, if they connect the units
+    pub fn implied_factor(&self, from: i32, to: i32) -> Option<f64> {
+        self.shortest_path(from, to).map(|(factor, _)| factor)
+    }
+
+    /// Every unit reachable from unit `from` within the graph's maximum path length, with the
+    /// factor converting a quantity in unit `from` to it, ordered by unit id
+    pub fn reachable(&self, from: i32) -> Vec<(i32, f64)> {
+        let mut paths = HashMap::from([(from, (1.0, 0))]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(unit) = queue.pop_front() {
+            let (factor, length) = paths[&unit];
+            if length >= self.max_path_length {
+                continue;
+            }
+            for &(next, edge_factor) in self.edges.get(&unit).into_iter().flatten() {
+                if paths.contains_key(&next) {
+                    continue;
+                }
+                paths.insert(next, (factor * edge_factor, length + 1));
+                queue.push_back(next);
+            }
+        }
+
+        let mut reachable: Vec<(i32, f64)> = paths
+            .into_iter()
+            .filter(|&(unit, _)| unit != from)
+            .map(|(unit, (factor, _))| (unit, factor))
+            .collect();
+        reachable.sort_by_key(|&(unit, _)| unit);
+        reachable
+    }
+
+    /// `quantity` in unit `unit` expressed in the largest unit it is at least 1 of, for display
+    ///
+    /// Returns the unit and the converted quantity (rounded to 6 decimal places, to hide the noise
+    /// of the conversion), which stay `unit` and `quantity` if no other unit fits better.
+    pub fn display(&self, unit: i32, quantity: f64) -> (i32, f64) {
+        self.reachable(unit)
+            .into_iter()
+            .map(|(to, factor)| (to, round_half_even(quantity * factor, 6)))
+            .filter(|&(_, converted)| converted.abs() >= 1.0 && converted.abs() < quantity.abs())
+            .min_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+            .unwrap_or((unit, quantity))
+    }
+
+    /// The factor and number of conversions of the path with the fewest conversions from unit
+    /// `from` to unit `to`
+    fn shortest_path(&self, from: i32, to: i32) -> Option<(f64, usize)> {
+        if from == to {
+            return Some((1.0, 0));
+        }
+
+        let mut paths = HashMap::from([(from, (1.0, 0))]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(unit) = queue.pop_front() {
+            let (factor, length) = paths[&unit];
+            for &(next, edge_factor) in self.edges.get(&unit).into_iter().flatten() {
+                if paths.contains_key(&next) {
+                    continue;
+                }
+                if next == to {
+                    return Some((factor * edge_factor, length + 1));
+                }
+                paths.insert(next, (factor * edge_factor, length + 1));
+                queue.push_back(next);
+            }
+        }
+
+        None
+    }
+}
+
+/// Rounds `value` to `decimal_places` decimal places, rounding ties to the even digit
+pub fn round_half_even(value: f64, decimal_places: u32) -> f64 {
+    let scale = 10f64.powi(decimal_places as i32);
+    (value * scale).round_ties_even() / scale
+}
+
+/// Whether factors `a` and `b` differ by more than `tolerance`, relative to the larger of them
+pub fn factors_conflict(a: f64, b: f64, tolerance: f64) -> bool {
+    (a - b).abs() > tolerance * a.abs().max(b.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAM: i32 = 1;
+    const KILOGRAM: i32 = 2;
+    const TONNE: i32 = 3;
+    const LITRE: i32 = 4;
+    const MILLILITRE: i32 = 5;
+
+    /// Grams, kilograms and tonnes chained through kilograms, and litres and millilitres not
+    /// connected to them
+    fn mass_graph(max_path_length: usize) -> ConversionGraph {
+        ConversionGraph::new(
+            [
+                (KILOGRAM, GRAM, 1000.0),
+                (TONNE, KILOGRAM, 1000.0),
+                (LITRE, MILLILITRE, 1000.0),
+            ],
+            max_path_length,
+        )
+    }
+
+    #[test]
+    fn reverse_conversions_use_the_reciprocal_factor() {
+        let graph = mass_graph(5);
+
+        assert!(matches!(graph.factor(KILOGRAM, GRAM), Ok(factor) if factor == 1000.0));
+        assert!(matches!(graph.factor(GRAM, KILOGRAM), Ok(factor) if factor == 0.001));
+        assert_eq!(graph.implied_factor(GRAM, GRAM), Some(1.0));
+    }
+
+    #[test]
+    fn paths_longer_than_the_maximum_are_rejected() {
+        let graph = mass_graph(1);
+
+        assert!(graph.factor(GRAM, KILOGRAM).is_ok());
+        assert!(matches!(
+            graph.factor(GRAM, TONNE),
+            Err(ConversionError::PathTooLong { length: 2 })
+        ));
+        assert!(matches!(
+            graph.factor(GRAM, LITRE),
+            Err(ConversionError::NoPath)
+        ));
+        assert!(graph.implied_factor(GRAM, TONNE).is_some());
+        assert_eq!(graph.reachable(GRAM), vec![(KILOGRAM, 0.001)]);
+    }
+
+    #[test]
+    fn display_picks_the_largest_unit_at_least_1() {
+        let graph = mass_graph(5);
+
+        assert_eq!(graph.display(GRAM, 2500.0), (KILOGRAM, 2.5));
+        assert_eq!(graph.display(GRAM, 2_500_000.0), (TONNE, 2.5));
+        assert_eq!(graph.display(GRAM, 500.0), (GRAM, 500.0));
+        assert_eq!(graph.display(KILOGRAM, 0.5), (KILOGRAM, 0.5));
+    }
+
+    #[test]
+    fn round_half_even_rounds_ties_to_the_even_digit() {
+        assert_eq!(round_half_even(2.5, 0), 2.0);
+        assert_eq!(round_half_even(3.5, 0), 4.0);
+        assert_eq!(round_half_even(0.125, 2), 0.12);
+        assert_eq!(round_half_even(0.375, 2), 0.38);
+        assert_eq!(round_half_even(-2.5, 0), -2.0);
+    }
+
+    #[test]
+    fn factors_conflict_beyond_the_tolerance() {
+        assert!(!factors_conflict(1.0, 2.0, 0.5));
+        assert!(factors_conflict(1.0, 2.0, 0.25));
+        assert!(!factors_conflict(2.0, 1.0, 0.5));
+        assert!(!factors_conflict(1000.0, 1000.5, DEFAULT_FACTOR_TOLERANCE));
+        assert!(factors_conflict(1000.0, 1002.0, DEFAULT_FACTOR_TOLERANCE));
+    }
+}
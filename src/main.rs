@@ -1,21 +1,50 @@
-use std::{borrow::Cow, env};
+mod cache;
+mod conversions;
+mod openfoodfacts;
 
-use chrono::{NaiveDate, NaiveDateTime};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use futures_util::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use poem::{
-    error::InternalServerError,
+    error::{InternalServerError, ParsePathError},
+    get, handler,
+    http::{header, HeaderValue, Method, StatusCode},
     listener::TcpListener,
+    middleware::Cors,
     web::{Data, Path},
-    EndpointExt, Result, Route, Server,
+    Endpoint, EndpointExt, IntoEndpoint, IntoResponse, Middleware, Request, Response, Result,
+    Route, Server,
 };
 use poem_openapi::{
-    payload::{Json, PlainText},
-    types::ToJSON,
-    ApiResponse, Object, OpenApi, OpenApiService,
+    param::Query,
+    payload::{EventStream, Json, PlainText},
+    types::{MaybeUndefined, ParseFromJSON, ParseFromParameter, ToJSON},
+    ApiResponse, Enum, Object, OpenApi, OpenApiService, Tags,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 
-#[derive(Object)]
+use crate::cache::ReportCache;
+use crate::conversions::{
+    factors_conflict, round_half_even, ConversionError, ConversionGraph, DEFAULT_FACTOR_TOLERANCE,
+};
+use crate::openfoodfacts::{LookupError, OpenFoodFacts};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Notify,
+};
+
+#[derive(Object, Clone)]
 struct Product {
     /// The id of the product
     #[oai(read_only)]
@@ -33,9 +62,84 @@ struct Product {
     /// The factor of purchase unit to stock unit
     /// (**e.g.** 1 carton of eggs is equivalent to 12 eggs in stock, so the factor would be *12.0*)
     purchase_to_stock_factor: Option<f32>,
+    /// The total stock below which the product should be restocked, in the stock unit
+    #[oai(validator(minimum(value = "0")))]
+    min_stock: Option<f32>,
+    /// The total stock above which the product is overstocked, in the stock unit
+    #[oai(validator(minimum(value = "0")))]
+    max_stock: Option<f32>,
+    /// The product's barcode (EAN/UPC)
+    barcode: Option<String>,
+    /// Whether the product has been archived, hiding it from listings
+    #[oai(read_only)]
+    archived: bool,
+    /// Which of the product's lots are used up first (defaults to `fefo`)
+    #[oai(default)]
+    depletion_strategy: DepletionStrategy,
+}
+
+/// The fields of a `Product` to change, leaving out the ones to keep
+#[derive(Object)]
+struct ProductPatch {
+    /// The new name of the product
+    name: Option<String>,
+    /// The new description, or `null` to remove it
+    description: MaybeUndefined<String>,
+    /// The new parent product id, or `null` to remove the parent
+    parent_product_id: MaybeUndefined<i32>,
+    /// The new purchase `Unit` id, or `null` to remove it
+    purchase_unit_id: MaybeUndefined<i32>,
+    /// The new stock `Unit` id, or `null` to remove it
+    stock_unit_id: MaybeUndefined<i32>,
+    /// The new factor of purchase unit to stock unit, or `null` to remove it
+    purchase_to_stock_factor: MaybeUndefined<f32>,
+    /// The new restock threshold, in the stock unit, or `null` to remove it
+    #[oai(validator(minimum(value = "0")))]
+    min_stock: MaybeUndefined<f32>,
+    /// The new overstock threshold, in the stock unit, or `null` to remove it
+    #[oai(validator(minimum(value = "0")))]
+    max_stock: MaybeUndefined<f32>,
+    /// The new barcode (EAN/UPC), or `null` to remove it
+    barcode: MaybeUndefined<String>,
+    /// Which of the product's lots are used up first from now on
+    depletion_strategy: Option<DepletionStrategy>,
+}
+
+#[derive(Enum, sqlx::Type, Debug, Default, Clone, Copy, PartialEq)]
+#[oai(rename_all = "lowercase")]
+#[sqlx(type_name = "depletion_strategy", rename_all = "lowercase")]
+enum DepletionStrategy {
+    /// First expired, first out: the lot with the soonest best by date
+    #[default]
+    Fefo,
+    /// First in, first out: the lot received first
+    Fifo,
+}
+
+#[derive(Enum)]
+#[oai(rename_all = "lowercase")]
+enum TagMode {
+    /// Products need all of the tags
+    All,
+    /// Products need at least one of the tags
+    Any,
+}
+
+#[derive(Object)]
+struct NewTag {
+    /// The tag, which is trimmed and lowercased
+    /// (**e.g.** vegan)
+    tag: String,
 }
 
 #[derive(Object)]
+struct NewUnitAlias {
+    /// The alias, which is trimmed and lowercased
+    /// (**e.g.** kilo)
+    alias: String,
+}
+
+#[derive(Object, Clone)]
 struct Space {
     /// The id of the space
     #[oai(read_only)]
@@ -46,6 +150,15 @@ struct Space {
     description: Option<String>,
 }
 
+/// The fields of a `Space` to change, leaving out the ones to keep
+#[derive(Object)]
+struct SpacePatch {
+    /// The new name of the space
+    name: Option<String>,
+    /// The new description, or `null` to remove it
+    description: MaybeUndefined<String>,
+}
+
 #[derive(Object)]
 struct Place {
     /// The id of the place
@@ -57,6 +170,15 @@ struct Place {
     description: Option<String>,
 }
 
+/// The fields of a `Place` to change, leaving out the ones to keep
+#[derive(Object)]
+struct PlacePatch {
+    /// The new name of the place
+    name: Option<String>,
+    /// The new description, or `null` to remove it
+    description: MaybeUndefined<String>,
+}
+
 #[derive(Object)]
 struct Unit {
     /// The id of the unit
@@ -68,6 +190,25 @@ struct Unit {
     /// The plural form of the unit, if applicable
     /// (**e.g.** grams)
     plural: Option<String>,
+    /// The number of decimal places quantities in this unit are rounded to, if any
+    /// (**e.g.** *0* for a discrete unit like eggs)
+    #[oai(validator(minimum(value = "0"), maximum(value = "6")))]
+    decimal_places: Option<i16>,
+    /// Whether the unit has been archived, as it was deleted while still in use
+    #[oai(read_only)]
+    archived: bool,
+}
+
+/// The fields of a `Unit` to change, leaving out the ones to keep
+#[derive(Object)]
+struct UnitPatch {
+    /// The new singular form of the unit
+    singular: Option<String>,
+    /// The new plural form, or `null` to remove it
+    plural: MaybeUndefined<String>,
+    /// The new number of decimal places quantities are rounded to, or `null` to stop rounding
+    #[oai(validator(minimum(value = "0"), maximum(value = "6")))]
+    decimal_places: MaybeUndefined<i16>,
 }
 
 #[derive(Object)]
@@ -81,6 +222,37 @@ struct UnitConversion {
     to_unit_id: i32,
     /// The factor from unit to unit
     factor: Option<f32>,
+    /// When the conversion was deleted, if it was
+    #[oai(read_only)]
+    deleted_at: Option<NaiveDateTime>,
+}
+
+/// The fields of a `UnitConversion` to change, leaving out the ones to keep
+#[derive(Object)]
+struct UnitConversionPatch {
+    /// The new id of the unit to convert from
+    from_unit_id: Option<i32>,
+    /// The new id of the unit to convert to
+    to_unit_id: Option<i32>,
+    /// The new factor from unit to unit, or `null` to remove it
+    factor: MaybeUndefined<f32>,
+}
+
+#[derive(Object)]
+struct ConversionConflict {
+    /// The id of the first unit conversion
+    first_id: i32,
+    /// The id of the second unit conversion, converting between the same units
+    second_id: i32,
+    /// The id of the unit the first conversion converts from
+    from_unit_id: i32,
+    /// The id of the unit the first conversion converts to
+    to_unit_id: i32,
+    /// The factor of the first conversion
+    first_factor: f64,
+    /// The factor of the second conversion, in the direction of the first (so the reciprocal if
+    /// it converts the other way)
+    second_factor: f64,
 }
 
 #[derive(Object)]
@@ -93,6 +265,73 @@ struct StockItem {
     best_by_date: Option<NaiveDate>,
 }
 
+/// The fields of a `StockItem` to change, leaving out the ones to keep
+#[derive(Object)]
+struct StockItemPatch {
+    /// The new id of the product
+    product_id: Option<i32>,
+    /// The new id of the `Space` the stock is in
+    space_id: Option<i32>,
+    /// The new quantity in stock, in the product's stock unit
+    stock_quantity: Option<f32>,
+    /// The new best by date, or `null` to remove it
+    best_by_date: MaybeUndefined<NaiveDate>,
+}
+
+#[derive(Enum, PartialEq)]
+#[oai(rename_all = "lowercase")]
+enum StockItemRelation {
+    Product,
+    Space,
+}
+
+#[derive(Object)]
+struct StockItemRelations {
+    /// The product the stock item holds
+    #[oai(skip_serializing_if_is_none)]
+    product: Option<Product>,
+    /// The space the stock item is in
+    #[oai(skip_serializing_if_is_none)]
+    space: Option<Space>,
+}
+
+#[derive(Object)]
+struct ExpandedStockItem {
+    #[oai(flatten)]
+    item: StockItem,
+    /// The related resources requested through `expand`
+    #[oai(rename = "_expanded", skip_serializing_if_is_none)]
+    expanded: Option<StockItemRelations>,
+}
+
+#[derive(Object)]
+struct StockItemDetail {
+    /// The id of the stock item
+    id: i32,
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    product_name: String,
+    /// The id of the space
+    space_id: i32,
+    /// The name of the space
+    space_name: String,
+    /// The quantity in stock, in the product's stock unit
+    stock_quantity: f32,
+    /// The `Unit` id of the product's stock unit
+    stock_unit_id: Option<i32>,
+    /// The quantity in stock, in the product's purchase unit
+    /// (**e.g.** *2.0* cartons for 24 eggs), if the product has a purchase to stock factor
+    purchase_unit_quantity: Option<f32>,
+    /// The `Unit` id of the product's purchase unit
+    purchase_unit_id: Option<i32>,
+    /// The date the stock is best used by
+    best_by_date: Option<NaiveDate>,
+    /// The quantity in stock with its unit, for display
+    /// (**e.g.** 2.5 kilograms), if the product has a stock unit
+    display: Option<String>,
+}
+
 #[derive(Object)]
 struct StockEntry {
     #[oai(read_only)]
@@ -101,21 +340,64 @@ struct StockEntry {
     entry_timestamp: NaiveDateTime,
     entry_type: EntryType,
     stock_quantity: f32,
-    stock_item_i32: Option<i32>,
+    stock_item_id: Option<i32>,
     product_id: Option<i32>,
     place_id: Option<i32>,
     to_space_id: Option<i32>,
     price: Option<f32>,
     memo: Option<String>,
+    /// Whether the entry has been applied to stock, or is pending confirmation
+    #[oai(default)]
+    status: EntryStatus,
 }
 
-#[derive(sqlx::Type, Serialize, Deserialize)]
+#[derive(Object)]
+struct PlaceEntry {
+    #[oai(flatten)]
+    entry: StockEntry,
+    /// The name of the entry's product, if it has one
+    product_name: Option<String>,
+}
+
+#[derive(Object)]
+struct PlaceActivity {
+    /// The place the activity is of
+    place_id: i32,
+    /// The stock entries referencing the place, oldest first
+    entries: Vec<PlaceEntry>,
+    /// The number of confirmed `Purchase` entries from the place
+    purchase_count: i64,
+    /// The number of those entries without a price, which aren't part of the total spend
+    unpriced_purchase_count: i64,
+    /// The total spent on confirmed purchases from the place
+    total_spend: f64,
+}
+
+#[derive(Object)]
+struct DanglingStockEntry {
+    #[oai(flatten)]
+    entry: StockEntry,
+    /// The fields of the entry referencing rows that no longer exist
+    dangling_fields: Vec<String>,
+}
+
+#[derive(Enum, sqlx::Type, Debug, Default, Clone, Copy, PartialEq)]
+#[oai(rename_all = "lowercase")]
+#[sqlx(type_name = "entry_status", rename_all = "lowercase")]
+enum EntryStatus {
+    Pending,
+    #[default]
+    Confirmed,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy)]
 #[sqlx(type_name = "entry_type", rename_all = "lowercase")]
 enum EntryType {
     Purchase,
     Transfer,
     Consume,
     Expire,
+    Adjust,
 }
 
 impl poem_openapi::types::Type for EntryType {
@@ -148,499 +430,8661 @@ impl poem_openapi::types::Type for EntryType {
 
 impl poem_openapi::types::ToJSON for EntryType {
     fn to_json(&self) -> Option<serde_json::Value> {
-        self.to_json()
+        serde_json::to_value(self).ok()
     }
 }
 
 impl poem_openapi::types::ParseFromJSON for EntryType {
     fn parse_from_json(value: Option<serde_json::Value>) -> poem_openapi::types::ParseResult<Self> {
         let value = value.unwrap_or_default();
-        if let serde_json::Value::String(value) = value {
-            Ok(serde_json::from_str(&value).unwrap())
+        if let serde_json::Value::String(_) = value {
+            serde_json::from_value(value).map_err(poem_openapi::types::ParseError::custom)
         } else {
             Err(poem_openapi::types::ParseError::expected_type(value))
         }
     }
 }
 
-type GetAllResponse<T> = Json<Vec<T>>;
+impl EntryType {
+    /// The name of the entry type, for display
+    fn label(&self) -> &'static str {
+        match self {
+            EntryType::Purchase => "Purchase",
+            EntryType::Transfer => "Transfer",
+            EntryType::Consume => "Consume",
+            EntryType::Expire => "Expire",
+            EntryType::Adjust => "Adjustment",
+        }
+    }
 
-#[derive(ApiResponse)]
-enum GetResponse<T: std::marker::Send + ToJSON> {
-    #[oai(status = 200)]
-    Success(Json<T>),
-    #[oai(status = 404)]
-    NotFound(PlainText<String>),
+    /// How an entry of this type changes the stock
+    fn effect(&self) -> EntryEffect {
+        match self {
+            EntryType::Purchase => EntryEffect::Add,
+            EntryType::Transfer => EntryEffect::Move,
+            EntryType::Consume | EntryType::Expire => EntryEffect::Remove,
+            EntryType::Adjust => EntryEffect::Adjust,
+        }
+    }
 }
 
-#[derive(ApiResponse)]
-enum DeleteResponse {
-    #[oai(status = 200)]
-    Success(Json<i32>),
-    #[oai(status = 404)]
-    NotFound(PlainText<String>),
+#[derive(Enum)]
+#[oai(rename_all = "lowercase")]
+enum EntryEffect {
+    /// Adds the quantity to the stock item
+    Add,
+    /// Removes the quantity from the stock item
+    Remove,
+    /// Removes the quantity from the stock item and adds it to the same lot in `to_space_id`
+    Move,
+    /// Adds the quantity to the stock item, which removes stock if it is negative
+    Adjust,
 }
 
-struct UkisApi;
+#[derive(Object)]
+struct EntryTypeInfo {
+    /// The entry type, as used in `StockEntry.entry_type`
+    value: EntryType,
+    /// The name of the entry type, for display
+    label: String,
+    /// How an entry of the type changes the stock
+    effect: EntryEffect,
+}
 
-#[OpenApi]
-impl UkisApi {
-    // PRODUCTS
-    /// Products: Fetch all
-    #[oai(path = "/products", method = "get")]
-    async fn get_products(&self, pool: Data<&PgPool>) -> Result<GetAllResponse<Product>> {
-        let products = sqlx::query_as!(Product, "SELECT * FROM products")
-            .fetch_all(pool.0)
-            .await
-            .unwrap();
+#[derive(Object)]
+struct DwellTime {
+    /// The id of the product
+    product_id: i32,
+    /// The average number of days between purchasing and consuming the product, weighted by
+    /// quantity, or null if nothing consumed could be matched to a purchase
+    average_days: Option<f64>,
+    /// The number of `Consume` entries matched to purchases
+    sample_count: i64,
+    /// The total quantity matched between purchases and consumption
+    matched_quantity: f64,
+    /// The quantity consumed without an earlier purchase to match it to
+    unmatched_quantity: f64,
+}
 
-        Ok(Json(products))
-    }
+#[derive(Object)]
+struct ComparedProduct {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The quantity of the product in space `a`, in its stock unit
+    a_quantity: Option<f64>,
+    /// The quantity of the product in space `b`, in its stock unit
+    b_quantity: Option<f64>,
+}
 
-    /// Products: Fetch by id
-    #[oai(path = "/products/:id", method = "get")]
-    async fn get_product(
-        &self,
-        pool: Data<&PgPool>,
-        id: Path<i32>,
-    ) -> Result<GetResponse<Product>> {
-        let result: Option<Product> =
-            sqlx::query_as!(Product, "SELECT * FROM products WHERE id = $1", id.0)
-                .fetch_optional(pool.0)
-                .await
-                .map_err(InternalServerError)?;
+#[derive(Object)]
+struct SpaceComparison {
+    /// The products only in space `a`
+    only_a: Vec<ComparedProduct>,
+    /// The products only in space `b`
+    only_b: Vec<ComparedProduct>,
+    /// The products in both spaces
+    both: Vec<ComparedProduct>,
+}
 
-        match result {
-            Some(product) => Ok(GetResponse::Success(Json(product))),
-            None => Ok(GetResponse::NotFound(PlainText(
-                format!("No product with id '{}' found.", id.0),
-            ))),
-        }
-    }
+#[derive(Object)]
+struct SpaceRunway {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The quantity of the product in the space, in its stock unit
+    stock_quantity: f64,
+    /// The average quantity of the product consumed from the space per day
+    daily_rate: f64,
+    /// The number of days until the space runs out of the product at that rate, or null if none
+    /// of it was consumed from the space
+    days_remaining: Option<f64>,
+}
 
-    /// Products: Create new
-    #[oai(path = "/products", method = "post")]
-    async fn new_product(&self, pool: Data<&PgPool>, product: Json<Product>) -> Result<Json<i32>> {
-        let record = sqlx::query!(
-            r#"
-INSERT INTO products (name, description, parent_product_id, purchase_unit_id, stock_unit_id, purchase_to_stock_factor)
-VALUES ($1, $2, $3, $4, $5, $6)
-RETURNING id"#,
-            product.name,
-            product.description,
-            product.parent_product_id,
-            product.purchase_unit_id,
-            product.stock_unit_id,
-            product.purchase_to_stock_factor
-        )
-        .fetch_one(pool.0)
-        .await
-        .map_err(InternalServerError)?;
+#[derive(Object)]
+struct MonthlyConsumption {
+    /// The calendar month, from 1 (January) to 12 (December)
+    month: i32,
+    /// The total quantity consumed in the month, in the product's stock unit
+    consumed: f64,
+}
 
-        Ok(Json(record.id))
-    }
+#[derive(Object)]
+struct Seasonality {
+    /// The id of the product
+    product_id: i32,
+    /// The number of years looked back over
+    years: i32,
+    /// The number of distinct calendar years the consumption within the window falls in
+    data_years: i64,
+    /// The consumption per calendar month, summed over the years
+    months: Vec<MonthlyConsumption>,
+}
 
-    /// Products: Delete with id
-    #[oai(path = "/products/:id", method = "delete")]
-    async fn delete_product(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<DeleteResponse> {
-        let result = sqlx::query!(
-            r#"
-DELETE FROM products
-WHERE id = $1
-RETURNING id"#,
-            id.0
-        )
-        .fetch_optional(pool.0)
-        .await
-        .map_err(InternalServerError)?;
+#[derive(Object)]
+struct PurchaseFrequency {
+    /// The id of the product
+    product_id: i32,
+    /// The number of days counted over
+    days: i32,
+    /// The number of `Purchase` entries within the window
+    purchase_count: i64,
+    /// The average number of days between consecutive purchases, or null if there were fewer
+    /// than two
+    average_interval_days: Option<f64>,
+}
 
-        match result {
-            Some(_) => Ok(DeleteResponse::Success(Json(id.0))),
-            None => Ok(DeleteResponse::NotFound(PlainText(
-                format!("No product with id '{}' found.", id.0),
-            ))),
-        }
-    }
+#[derive(Object)]
+struct ConsumptionRate {
+    /// The id of the product
+    product_id: i32,
+    /// The number of days the rate is averaged over
+    days: i32,
+    /// The `Unit` id of the product's stock unit, which all quantities are in
+    stock_unit_id: Option<i32>,
+    /// The total quantity consumed within the window
+    total_consumed: f64,
+    /// The average quantity consumed per day
+    daily_rate: f64,
+    /// The `Consume` entries within the window
+    entries: Vec<StockEntry>,
+}
 
-    // UNITS
-    /// Units: Fetch all
-    #[oai(path = "/units", method = "get")]
-    async fn get_units(&self, pool: Data<&PgPool>) -> Result<GetAllResponse<Unit>> {
-        let units = sqlx::query_as!(Unit, "SELECT * FROM units")
-            .fetch_all(pool.0)
-            .await
-            .unwrap();
+#[derive(Object)]
+struct LotStock {
+    /// The id of the stock item
+    stock_item_id: i32,
+    /// The id of the space
+    space_id: i32,
+    /// The name of the space
+    space_name: String,
+    /// The quantity in stock, in the product's stock unit
+    stock_quantity: f32,
+}
 
-        Ok(Json(units))
-    }
+#[derive(Object)]
+struct ProductLot {
+    /// The date the lot is best used by, null for undated stock
+    best_by_date: Option<NaiveDate>,
+    /// The total quantity of the lot, in the product's stock unit
+    total_quantity: f64,
+    /// The stock items holding the lot, per space
+    stock: Vec<LotStock>,
+}
 
-    /// Units: Fetch by id
-    #[oai(path = "/units/:id", method = "get")]
-    async fn get_unit(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<GetResponse<Unit>> {
-        let unit: Option<Unit> = sqlx::query_as!(Unit, "SELECT * FROM units WHERE id = $1", id.0)
-            .fetch_optional(pool.0)
-            .await
-            .map_err(InternalServerError)?;
+#[derive(Object)]
+struct DistributedStock {
+    /// The id of the stock item
+    stock_item_id: i32,
+    /// The id of the space
+    space_id: i32,
+    /// The name of the space
+    space_name: String,
+    /// The quantity in stock, in the product's stock unit
+    stock_quantity: f32,
+    /// The date the stock is best used by
+    best_by_date: Option<NaiveDate>,
+}
 
-        match unit {
-            Some(unit) => Ok(GetResponse::Success(Json(unit))),
-            None => Ok(GetResponse::NotFound(PlainText(
-                format!("No unit with id '{}' found.", id.0),
-            ))),
-        }
-    }
+#[derive(Object)]
+struct ProductDistribution {
+    /// The id of the product
+    product_id: i32,
+    /// The product's stock items, per space
+    stock: Vec<DistributedStock>,
+    /// The total quantity in stock, in the product's stock unit
+    total_quantity: f64,
+}
 
-    /// Units: Create new
-    #[oai(path = "/units", method = "post")]
-    async fn new_unit(&self, pool: Data<&PgPool>, unit: Json<Unit>) -> Result<Json<i32>> {
-        let record = sqlx::query!(
-            r#"
-INSERT INTO units (singular, plural)
-VALUES ($1, $2)
-RETURNING id"#,
-            unit.singular,
-            unit.plural,
-        )
-        .fetch_one(pool.0)
-        .await
-        .map_err(InternalServerError)?;
+#[derive(Object)]
+struct SpaceShare {
+    /// The id of the space
+    space_id: i32,
+    /// The name of the space
+    space_name: String,
+    /// The quantity of the product in the space, in its stock unit
+    stock_quantity: f64,
+    /// The space's share of the product's total stock, in percent
+    percentage: f64,
+}
 
-        Ok(Json(record.id))
-    }
+#[derive(Object)]
+struct BestByDateChange {
+    /// The best by date of the stock items to change, null for undated stock
+    from_date: Option<NaiveDate>,
+    /// The new best by date, null to make the stock undated
+    to_date: Option<NaiveDate>,
+}
 
-    /// Units: Delete with id
-    #[oai(path = "/units/:id", method = "delete")]
-    async fn delete_unit(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<DeleteResponse> {
-        let result = sqlx::query!(
-            r#"
-DELETE FROM units
-WHERE id = $1
-RETURNING id"#,
-            id.0
+#[derive(Object)]
+struct ThresholdUpdate {
+    /// The id of the product
+    product_id: i32,
+    /// The new reorder threshold (the product's minimum stock), in the product's stock unit
+    reorder_threshold: f32,
+}
+
+#[derive(Object)]
+struct ThresholdUpdateResult {
+    /// The id of the product
+    product_id: i32,
+    /// The reorder threshold before the update
+    previous_threshold: Option<f32>,
+    /// The reorder threshold after the update
+    reorder_threshold: f32,
+}
+
+#[derive(Object)]
+struct UnitQuantity {
+    /// The quantity
+    quantity: f64,
+    /// The `Unit` id of the unit the quantity is in
+    unit_id: i32,
+}
+
+#[derive(Object)]
+struct NormalizedQuantity {
+    /// The id of the product
+    product_id: i32,
+    /// The `Unit` id of the product's stock unit
+    stock_unit_id: i32,
+    /// The quantity in the product's stock unit
+    quantity: f64,
+    /// The factor the quantity was converted with
+    factor: f64,
+}
+
+#[derive(Enum)]
+#[oai(rename_all = "lowercase")]
+enum PurchaseFactorSource {
+    /// The product's own `purchase_to_stock_factor`
+    Product,
+    /// Derived from the unit conversions between the purchase and stock units
+    Conversions,
+}
+
+#[derive(Object)]
+struct PurchaseFactor {
+    /// The id of the product
+    product_id: i32,
+    /// The `Unit` id of the product's purchase unit
+    purchase_unit_id: Option<i32>,
+    /// The `Unit` id of the product's stock unit
+    stock_unit_id: Option<i32>,
+    /// The factor of purchase unit to stock unit
+    factor: f64,
+    /// Where the factor comes from
+    source: PurchaseFactorSource,
+}
+
+#[derive(Object)]
+struct UnitEquivalent {
+    /// The id of the `Unit` converted to
+    to_unit_id: i32,
+    /// The factor converting a quantity in the requested unit to this unit, across every
+    /// conversion on the way
+    cumulative_factor: f64,
+}
+
+#[derive(Enum)]
+#[oai(rename_all = "lowercase")]
+enum WasteGrouping {
+    Product,
+    Space,
+}
+
+#[derive(Object)]
+struct WasteGroup {
+    /// The id of the product or space, none for the waste that can't be assigned to one
+    id: Option<i32>,
+    /// The name of the product or space
+    name: Option<String>,
+    /// The number of `Expire` entries in the group
+    entry_count: i64,
+    /// The total quantity expired, in stock units
+    total_quantity: f64,
+    /// The estimated value of the expired quantity, based on the latest purchase price of each product
+    estimated_value: Option<f64>,
+}
+
+#[derive(Object)]
+struct WasteReport {
+    /// The waste per product or space, most wasted first
+    groups: Vec<WasteGroup>,
+    /// The total number of `Expire` entries
+    entry_count: i64,
+    /// The total quantity expired, in stock units
+    total_quantity: f64,
+    /// The total estimated value expired
+    estimated_value: f64,
+}
+
+#[derive(Object, Clone)]
+struct ProductTurnover {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The quantity consumed within the period, in stock units
+    consumed: f64,
+    /// The total stock at the start of the period
+    opening_stock: f64,
+    /// The total stock at the end of the period
+    closing_stock: f64,
+    /// The average of the opening and closing stock
+    average_stock: f64,
+    /// The consumption divided by the average stock, null when there was no average stock
+    turnover_ratio: Option<f64>,
+}
+
+#[derive(Object)]
+struct ProductStockTotal {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The total quantity in stock, in the product's stock unit
+    total_quantity: f64,
+    /// The `Unit` id of the product's stock unit
+    stock_unit_id: Option<i32>,
+    /// The total quantity in the display unit
+    display_quantity: f64,
+    /// The `Unit` id of the largest unit the total is at least 1 of, for display
+    display_unit_id: Option<i32>,
+    /// The number of stock items holding the product
+    stock_item_count: i64,
+}
+
+#[derive(Object)]
+struct StockTotals {
+    /// When the cached totals were last refreshed
+    stale_as_of: Option<NaiveDateTime>,
+    /// The total stock per product
+    totals: Vec<ProductStockTotal>,
+}
+
+#[derive(Enum)]
+#[oai(rename_all = "snake_case")]
+enum StockRangeStatus {
+    /// The total stock is below the product's minimum
+    BelowMin,
+    /// The total stock is above the product's maximum
+    AboveMax,
+}
+
+#[derive(Object)]
+struct OutOfRangeProduct {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The total quantity in stock, in the product's stock unit
+    total_quantity: f64,
+    /// The product's minimum stock
+    min_stock: Option<f32>,
+    /// The product's maximum stock
+    max_stock: Option<f32>,
+    /// Which end of the range the total stock is outside of
+    status: StockRangeStatus,
+}
+
+#[derive(Object)]
+struct RestockProduct {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The total quantity in stock, in the product's stock unit
+    total_quantity: f64,
+    /// The quantity to restock to, the product's maximum stock or else its minimum stock
+    target_quantity: f64,
+    /// The quantity to buy to reach the target
+    reorder_quantity: f64,
+    /// The latest purchase price per stock unit of the product
+    unit_price: Option<f64>,
+    /// The reorder quantity multiplied by the unit price
+    estimated_cost: Option<f64>,
+    /// Whether a purchase price is known for the product
+    priced: bool,
+}
+
+#[derive(Object)]
+struct RestockCost {
+    /// The products below their minimum stock
+    products: Vec<RestockProduct>,
+    /// The estimated cost of restocking all priced products
+    total_cost: f64,
+    /// The ids of the products without a known price, which aren't part of the total cost
+    unpriced_product_ids: Vec<i32>,
+}
+
+#[derive(Object)]
+struct StockTarget {
+    /// The id of the product
+    product_id: i32,
+    /// The total quantity to have in stock, in the product's stock unit
+    #[oai(validator(minimum(value = "0")))]
+    target_quantity: f64,
+}
+
+#[derive(Object)]
+struct PlannedPurchase {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The total quantity in stock, in the product's stock unit
+    total_quantity: f64,
+    /// The quantity to have in stock, in the product's stock unit
+    target_quantity: f64,
+    /// The quantity missing to reach the target, in the product's stock unit
+    shortfall: f64,
+    /// The `Unit` id of the product's purchase unit
+    purchase_unit_id: Option<i32>,
+    /// The number of purchase units to buy to cover the shortfall, or null if the purchase unit
+    /// can't be converted to the stock unit
+    purchase_quantity: Option<f64>,
+    /// The latest purchase price per stock unit of the product
+    unit_price: Option<f64>,
+    /// The cost of the purchase units to buy, or of the shortfall if they aren't known
+    estimated_cost: Option<f64>,
+}
+
+#[derive(Object)]
+struct PurchasePlan {
+    /// The purchases to make, in the order the targets were given in
+    products: Vec<PlannedPurchase>,
+    /// The estimated cost of all priced purchases
+    total_cost: f64,
+    /// The ids of the products without a known price, which aren't part of the total cost
+    unpriced_product_ids: Vec<i32>,
+}
+
+#[derive(Object)]
+struct ProjectedWasteProduct {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The average quantity consumed per day over the last 30 days
+    daily_rate: f64,
+    /// The quantity in stock expiring within the window
+    expiring_quantity: f64,
+    /// The part of the expiring quantity that won't be consumed before it expires at that rate
+    projected_waste_quantity: f64,
+    /// The latest purchase price per stock unit of the product
+    unit_price: Option<f64>,
+    /// The projected waste quantity multiplied by the unit price
+    projected_waste_value: Option<f64>,
+}
+
+#[derive(Object)]
+struct ProjectedWaste {
+    /// The number of days ahead expiring stock is projected for
+    within_days: i32,
+    /// The products projected to waste stock, most valuable waste first
+    products: Vec<ProjectedWasteProduct>,
+    /// The total value of the projected waste of all priced products
+    total_value: f64,
+}
+
+#[derive(Object)]
+struct StaleStockItem {
+    /// The id of the stock item
+    id: i32,
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    product_name: String,
+    /// The id of the space
+    space_id: i32,
+    /// The name of the space
+    space_name: String,
+    /// The quantity in stock, in stock units
+    stock_quantity: f32,
+    /// The stock item's best by date, if it has one
+    best_by_date: Option<NaiveDate>,
+    /// When the stock item was created
+    created_at: NaiveDateTime,
+    /// The number of whole days since the stock item was created
+    age_days: i32,
+}
+
+#[derive(Object, Clone)]
+struct StockItemValue {
+    /// The id of the stock item
+    id: i32,
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    product_name: String,
+    /// The id of the space
+    space_id: i32,
+    /// The name of the space
+    space_name: String,
+    /// The quantity in stock, in stock units
+    stock_quantity: f32,
+    /// The latest purchase price per stock unit of the product
+    unit_price: Option<f64>,
+    /// The stock quantity multiplied by the unit price
+    value: Option<f64>,
+    /// Whether a purchase price is known for the product
+    priced: bool,
+}
+
+#[derive(Object)]
+struct InventorySnapshot {
+    /// The id of the snapshot row
+    id: i32,
+    /// When the snapshot was taken
+    taken_at: NaiveDateTime,
+    /// The id of the product
+    product_id: i32,
+    /// The id of the space, or null for the product's total across all spaces
+    space_id: Option<i32>,
+    /// The total quantity in stock, in the product's stock unit
+    total_quantity: f64,
+}
+
+#[derive(Object)]
+struct ProductShrinkage {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The total quantity recorded by the snapshot
+    snapshot_quantity: f64,
+    /// The net quantity of the confirmed stock entries since the snapshot
+    net_entry_quantity: f64,
+    /// The quantity the entries account for, the snapshot quantity plus the net entry quantity
+    expected_quantity: f64,
+    /// The total quantity currently in stock
+    actual_quantity: f64,
+    /// The expected minus the actual quantity, positive for unexplained losses
+    discrepancy: f64,
+}
+
+#[derive(Object)]
+struct Shrinkage {
+    /// When the snapshot compared against was taken
+    snapshot_taken_at: NaiveDateTime,
+    /// Every product whose stock doesn't match its entries, largest discrepancy first
+    products: Vec<ProductShrinkage>,
+}
+
+#[derive(Object)]
+struct PriorityProduct {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The weighted sum of the expiry and shortage factors, higher is more urgent
+    score: f64,
+    /// How close the product's earliest best by date is, from 0 (beyond the horizon) to 1
+    /// (today or past)
+    expiry_factor: f64,
+    /// How far the product's total stock is below its minimum stock, from 0 (at or above it) to 1
+    /// (out of stock)
+    shortage_factor: f64,
+    /// The number of days until the earliest best by date of the product's stock in hand
+    days_until_expiry: Option<i32>,
+    /// The total quantity in stock, in the product's stock unit
+    total_quantity: f64,
+    /// The product's minimum stock
+    min_stock: Option<f32>,
+}
+
+#[derive(Object)]
+struct ReorderPriorityProduct {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The weighted sum of the shortage and run out factors, higher is more urgent
+    score: f64,
+    /// How far the product's total stock is below its minimum stock, from 0 (at or above it) to 1
+    /// (out of stock)
+    shortage_factor: f64,
+    /// How soon the product runs out at its consumption rate, from 0 (beyond the horizon or not
+    /// consumed) to 1 (out of stock)
+    run_out_factor: f64,
+    /// The total quantity in stock, in the product's stock unit
+    total_quantity: f64,
+    /// The product's minimum stock, its reorder point
+    min_stock: f32,
+    /// The average quantity consumed per day
+    daily_rate: f64,
+    /// The number of days until the product runs out at that rate, or null if it wasn't consumed
+    days_remaining: Option<f64>,
+}
+
+#[derive(Object)]
+struct SupplierSpend {
+    /// The id of the `Place` purchased from, or null for purchases without a place
+    place_id: Option<i32>,
+    /// The name of the place
+    name: Option<String>,
+    /// The number of `Purchase` entries within the period
+    purchase_count: i64,
+    /// The number of those entries without a price, which aren't part of the total spend
+    unpriced_purchase_count: i64,
+    /// The total spent within the period
+    total_spend: f64,
+}
+
+#[derive(Object)]
+struct ConsumedProduct {
+    /// The id of the product
+    product_id: i32,
+    /// The name of the product
+    name: String,
+    /// The `Unit` id of the product's stock unit, which the quantity is in
+    stock_unit_id: Option<i32>,
+    /// The number of `Consume` entries within the period
+    entry_count: i64,
+    /// The total quantity consumed within the period
+    total_quantity: f64,
+}
+
+/// The kinds of rows that are only marked as deleted, and can be restored
+#[derive(Enum)]
+#[oai(rename_all = "snake_case")]
+enum DeletableEntity {
+    UnitConversion,
+}
+
+#[derive(Enum)]
+#[oai(rename_all = "lowercase")]
+enum AgingGrouping {
+    Product,
+    Overall,
+}
+
+#[derive(Enum)]
+#[oai(rename_all = "snake_case")]
+enum AgingBucket {
+    /// The best by date has passed
+    Expired,
+    /// Best by within the next 7 days
+    ExpiringThisWeek,
+    /// Best by within the next 30 days
+    ExpiringThisMonth,
+    /// Best by in more than 30 days
+    Fresh,
+    /// Without a best by date
+    Undated,
+}
+
+#[derive(Object)]
+struct AgingGroup {
+    /// The id of the product, when grouped by product
+    product_id: Option<i32>,
+    /// The name of the product, when grouped by product
+    name: Option<String>,
+    /// How close the stock is to its best by date
+    bucket: AgingBucket,
+    /// The number of stock items in the bucket
+    stock_item_count: i64,
+    /// The total quantity in the bucket, in stock units unless converted
+    total_quantity: f64,
+    /// The `Unit` id of the unit the total quantity is in, null if it adds up the stock units of
+    /// several products
+    unit_id: Option<i32>,
+    /// Whether the quantity was converted to the requested unit, which is false for products
+    /// whose stock unit doesn't convert to it
+    converted: bool,
+}
+
+#[derive(Enum)]
+enum FreshnessBucket {
+    /// The best by date has passed
+    #[oai(rename = "expired")]
+    Expired,
+    /// Best by in 0 to 3 days
+    #[oai(rename = "0-3")]
+    Days0To3,
+    /// Best by in 4 to 7 days
+    #[oai(rename = "4-7")]
+    Days4To7,
+    /// Best by in 8 to 30 days
+    #[oai(rename = "8-30")]
+    Days8To30,
+    /// Best by in more than 30 days
+    #[oai(rename = "30+")]
+    Over30,
+    /// Without a best by date
+    #[oai(rename = "undated")]
+    Undated,
+}
+
+#[derive(Object)]
+struct FreshnessCount {
+    /// How many days are left until the best by date
+    bucket: FreshnessBucket,
+    /// The number of stock items in the bucket
+    stock_item_count: i64,
+    /// The total quantity in the bucket, adding up the stock units of all products
+    total_quantity: f64,
+}
+
+#[derive(Object)]
+struct SpaceDiversity {
+    /// The id of the space
+    space_id: i32,
+    /// The name of the space
+    name: String,
+    /// The number of distinct products stocked in the space
+    product_count: i64,
+    /// The number of stock items in the space
+    stock_item_count: i64,
+}
+
+#[derive(Enum)]
+#[oai(rename_all = "lowercase")]
+enum SearchResultType {
+    Product,
+    Space,
+    Place,
+}
+
+#[derive(Object)]
+struct SearchResult {
+    /// The type of the matching entity
+    #[oai(rename = "type")]
+    result_type: SearchResultType,
+    /// The id of the matching entity
+    id: i32,
+    /// The name of the matching entity
+    name: String,
+    /// How well the entity matches, from best to worst: *0* for an exact name match, *1* for a
+    /// name starting with the query, *2* for a name containing it, and *3* for a description
+    /// containing it
+    rank: i32,
+}
+
+#[derive(Object)]
+struct UpsertResult {
+    /// The id of the created or updated row
+    id: i32,
+    /// Whether the row was created, rather than updated
+    created: bool,
+}
+
+#[derive(Object)]
+struct FieldError {
+    /// The name of the offending field
+    field: String,
+    /// Why the field's value is invalid
+    message: String,
+}
+
+#[derive(Object)]
+struct EntryValidation {
+    /// Whether the entry passed all checks
+    valid: bool,
+    /// The checks the entry failed
+    errors: Vec<FieldError>,
+}
+
+#[derive(Object)]
+struct StockPurchase {
+    /// The id of the product purchased
+    product_id: i32,
+    /// The id of the `Space` to put the purchase in
+    space_id: i32,
+    /// The quantity purchased, in the product's purchase unit
+    #[oai(validator(minimum(value = "0", exclusive)))]
+    quantity: f32,
+    /// The best by date of the purchased lot
+    best_by_date: Option<NaiveDate>,
+    /// The price paid per purchase unit
+    #[oai(validator(minimum(value = "0")))]
+    price: Option<f32>,
+    /// The id of the `Place` purchased from
+    place_id: Option<i32>,
+    /// A note recorded with the `Purchase` entry
+    /// (**e.g.** on sale)
+    memo: Option<String>,
+}
+
+#[derive(Object)]
+struct StockConsumption {
+    /// The id of the product consumed
+    product_id: i32,
+    /// The quantity consumed, in the product's stock unit
+    #[oai(validator(minimum(value = "0", exclusive)))]
+    quantity: f32,
+    /// A note recorded with each of the `Consume` entries
+    memo: Option<String>,
+}
+
+#[derive(Object)]
+struct StockTransfer {
+    /// The id of the stock item to transfer from
+    stock_item_id: i32,
+    /// The id of the `Space` to transfer to
+    to_space_id: i32,
+    /// The quantity to transfer, in the product's stock unit (defaults to all of the stock item)
+    #[oai(validator(minimum(value = "0", exclusive)))]
+    quantity: Option<f32>,
+    /// Why the stock was moved, recorded with the `Transfer` entry
+    memo: Option<String>,
+}
+
+#[derive(Object)]
+struct StockExpiry {
+    /// The id of the stock item to discard from
+    stock_item_id: i32,
+    /// The quantity discarded, in the product's stock unit (defaults to all of the stock item)
+    #[oai(validator(minimum(value = "0", exclusive)))]
+    quantity: Option<f32>,
+    /// Why the stock was discarded
+    memo: Option<String>,
+    /// Whether to delete the stock item if nothing is left in it (defaults to false). Its stock
+    /// entries are kept, still referencing it.
+    delete_if_empty: Option<bool>,
+}
+
+#[derive(Object)]
+struct ExpiredStock {
+    /// The recorded `Expire` entry
+    entry: StockEntry,
+    /// The estimated value of the discarded quantity, based on the latest purchase price of the
+    /// product, if there is one
+    estimated_value: Option<f64>,
+    /// Whether the emptied stock item was deleted
+    deleted: bool,
+}
+
+#[derive(Object)]
+struct BulkTransfer {
+    /// The id of the `Space` to transfer from
+    from_space_id: i32,
+    /// The id of the `Space` to transfer to
+    to_space_id: i32,
+    /// The ids of the products to transfer all stock of
+    product_ids: Vec<i32>,
+}
+
+/// A stock item's new quantity, as sent to `/events/stock` subscribers
+///
+/// Deleted stock items are sent with a quantity of 0.
+#[derive(Object, Clone)]
+struct StockChange {
+    /// The id of the stock item
+    stock_item_id: i32,
+    /// The id of the product
+    product_id: i32,
+    /// The id of the space
+    space_id: i32,
+    /// The new quantity in stock, in stock units
+    stock_quantity: f32,
+}
+
+#[derive(Object)]
+struct RebuiltStockItem {
+    /// The id of the stock item
+    id: i32,
+    /// The quantity before the rebuild
+    before: f32,
+    /// The quantity replayed from the stock entries
+    after: f32,
+}
+
+#[derive(Object)]
+struct StockRebuild {
+    /// Whether the rebuilt quantities were only computed, not stored
+    dry_run: bool,
+    /// Every stock item, with its quantity before and after
+    items: Vec<RebuiltStockItem>,
+    /// The ids of the entries that couldn't be replayed, as their stock item no longer exists
+    skipped_entry_ids: Vec<i32>,
+    /// The ids of the `Transfer` entries whose destination couldn't be credited, as the stock
+    /// item they moved stock from is unknown. Only a dry run completes with any of them.
+    unknown_transfer_ids: Vec<i32>,
+}
+
+#[derive(Object)]
+struct PurgedRows {
+    /// The number of deleted unit conversions removed
+    unit_conversions: u64,
+}
+
+/// The rows of a table whose reference to another table is dangling
+struct DanglingIds {
+    count: i64,
+    sample_ids: Vec<i32>,
+}
+
+#[derive(Object)]
+struct DanglingReference {
+    /// The table holding the references
+    table: String,
+    /// The column holding the references
+    column: String,
+    /// The table referenced
+    referenced_table: String,
+    /// The number of rows referencing a row that doesn't exist
+    count: i64,
+    /// The ids of up to 10 of those rows
+    sample_ids: Vec<i32>,
+}
+
+#[derive(Object)]
+struct IntegrityReport {
+    /// Every reference checked, including those without dangling rows
+    references: Vec<DanglingReference>,
+    /// The total number of dangling references found
+    total_count: i64,
+}
+
+#[derive(Object)]
+struct ImportStep {
+    /// The table to insert into
+    table: String,
+    /// The tables it references, which have to be inserted into first
+    depends_on: Vec<String>,
+}
+
+#[derive(Object)]
+struct BatchOperation {
+    /// The creation endpoint to call (**e.g.** `/products`)
+    path: String,
+    /// The payload to call the endpoint with, in which strings like `"$0.id"` are replaced by the
+    /// id created by an earlier operation
+    body: serde_json::Value,
+}
+
+#[derive(Object)]
+struct BatchResult {
+    /// The id created by the operation
+    id: i32,
+}
+
+/// Why an operation of a batch failed
+enum OperationError {
+    Conflict(String),
+    Unprocessable(String),
+}
+
+/// Why a pending stock entry couldn't be confirmed
+enum ConfirmError {
+    NotFound(String),
+    Conflict(String),
+    Unprocessable(String),
+}
+
+/// Why a stock entry couldn't be recorded
+enum RecordError {
+    /// The entry itself is malformed
+    BadRequest(String),
+    /// The entry's change can't be applied to stock
+    Unprocessable(String),
+}
+
+impl<T: std::marker::Send + ToJSON> From<RecordError> for StockOperationResponse<T> {
+    fn from(err: RecordError) -> Self {
+        match err {
+            RecordError::BadRequest(reason) => {
+                StockOperationResponse::BadRequest(PlainText(reason))
+            }
+            RecordError::Unprocessable(reason) => {
+                StockOperationResponse::Unprocessable(PlainText(reason))
+            }
+        }
+    }
+}
+
+type GetAllResponse<T> = Json<Vec<T>>;
+
+#[derive(ApiResponse)]
+enum GetResponse<T: std::marker::Send + ToJSON> {
+    #[oai(status = 200)]
+    Success(Json<T>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum ReportResponse<T: std::marker::Send + ToJSON> {
+    #[oai(status = 200)]
+    Success(Json<T>),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum CachedReportResponse<T: std::marker::Send + ToJSON> {
+    #[oai(status = 200)]
+    Success(
+        Json<T>,
+        /// When the report was computed
+        #[oai(header = "X-Cached-At")]
+        String,
+        /// Whether the report was computed for this request, rather than taken from the cache
+        #[oai(header = "X-Cache-Fresh")]
+        bool,
+    ),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum UpdateResponse<T: std::marker::Send + ToJSON> {
+    #[oai(status = 200)]
+    Success(Json<T>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum EditResponse<T: std::marker::Send + ToJSON> {
+    #[oai(status = 200)]
+    Success(Json<T>),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum ConversionResponse<T: std::marker::Send + ToJSON> {
+    #[oai(status = 200)]
+    Success(Json<T>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum CreateResponse {
+    #[oai(status = 200)]
+    Success(Json<i32>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum ConversionCreateResponse {
+    #[oai(status = 200)]
+    Success(Json<i32>),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum AliasResponse {
+    #[oai(status = 200)]
+    Success(Json<Vec<String>>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum ImportResponse {
+    #[oai(status = 200)]
+    Success(Json<i32>),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+    #[oai(status = 502)]
+    BadGateway(PlainText<String>),
+    #[oai(status = 504)]
+    GatewayTimeout(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum BulkCreateResponse {
+    #[oai(status = 200)]
+    Success(Json<Vec<i32>>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum BatchResponse {
+    #[oai(status = 200)]
+    Success(Json<Vec<BatchResult>>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum UpsertResponse {
+    #[oai(status = 200)]
+    Success(Json<UpsertResult>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum BulkUpsertResponse {
+    #[oai(status = 200)]
+    Success(Json<Vec<UpsertResult>>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum RebuildResponse {
+    #[oai(status = 200)]
+    Success(Json<StockRebuild>),
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum EntryCreateResponse {
+    #[oai(status = 200)]
+    Success(Json<StockEntry>),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+    /// The checks the entry failed
+    #[oai(status = 422)]
+    Invalid(Json<Vec<FieldError>>),
+}
+
+#[derive(ApiResponse)]
+enum EntryDeleteResponse {
+    #[oai(status = 200)]
+    Success(Json<i32>),
+    #[oai(status = 204)]
+    NoContent,
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum ConfirmResponse {
+    #[oai(status = 200)]
+    Success(Json<StockEntry>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum TagResponse {
+    #[oai(status = 200)]
+    Success(Json<Vec<String>>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum ConfirmBatchResponse {
+    #[oai(status = 200)]
+    Success(Json<Vec<StockEntry>>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum StockOperationResponse<T: std::marker::Send + ToJSON> {
+    #[oai(status = 200)]
+    Success(Json<T>),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum ThresholdUpdateResponse {
+    #[oai(status = 200)]
+    Success(Json<Vec<ThresholdUpdateResult>>),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 422)]
+    Unprocessable(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum RestoreResponse {
+    #[oai(status = 200)]
+    Success(Json<UnitConversion>),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum DeleteResponse {
+    #[oai(status = 200)]
+    Success(Json<i32>),
+    #[oai(status = 204)]
+    NoContent,
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+}
+
+impl DeleteResponse {
+    /// The response to deleting row `id`, without a body if `no_content` is set
+    fn deleted(id: i32, no_content: Option<bool>) -> Self {
+        if no_content.unwrap_or(false) {
+            DeleteResponse::NoContent
+        } else {
+            DeleteResponse::Success(Json(id))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct VersionInfo {
+    /// The version of the crate
+    version: &'static str,
+    /// The (short) hash of the commit the server was built from
+    git_hash: &'static str,
+    /// When the server was built, in UTC
+    build_timestamp: Option<NaiveDateTime>,
+}
+
+/// Reports the version of the running server, outside of the API
+#[handler]
+fn version() -> poem::web::Json<VersionInfo> {
+    poem::web::Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+        build_timestamp: env!("BUILD_TIMESTAMP")
+            .parse()
+            .ok()
+            .and_then(|secs| NaiveDateTime::from_timestamp_opt(secs, 0)),
+    })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum HealthStatus {
+    /// The database can be reached and the pool has room for more requests
+    Ok,
+    /// The database can be reached, but every connection of the pool is in use
+    Degraded,
+    /// The database can't be reached
+    Unavailable,
+}
+
+#[derive(Serialize)]
+struct PoolMetrics {
+    /// The number of open connections
+    total: u32,
+    /// The number of open connections that are idle
+    idle: usize,
+    /// The number of open connections in use
+    in_use: usize,
+    /// The most connections the pool will open
+    max: u32,
+    /// How long acquiring a connection for this check took, in milliseconds
+    acquire_latency_ms: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: HealthStatus,
+    pool: PoolMetrics,
+}
+
+/// Reports whether the database can be reached, and how busy the connection pool is
+#[handler]
+async fn health(pool: Data<&PgPool>) -> (StatusCode, poem::web::Json<Health>) {
+    let total = pool.size();
+    let idle = pool.num_idle();
+    let max = pool.options().get_max_connections();
+
+    let start = Instant::now();
+    let ping = match pool.acquire().await {
+        Ok(mut conn) => {
+            let acquire_latency = start.elapsed();
+            sqlx::query!("SELECT 1 AS ping")
+                .fetch_one(&mut *conn)
+                .await
+                .map(|_| acquire_latency)
+        }
+        Err(err) => Err(err),
+    };
+
+    let saturated = idle == 0 && total >= max;
+    let (code, status) = match ping {
+        Ok(_) if saturated => (StatusCode::OK, HealthStatus::Degraded),
+        Ok(_) => (StatusCode::OK, HealthStatus::Ok),
+        Err(_) => (StatusCode::SERVICE_UNAVAILABLE, HealthStatus::Unavailable),
+    };
+
+    (
+        code,
+        poem::web::Json(Health {
+            status,
+            pool: PoolMetrics {
+                total,
+                idle,
+                in_use: total as usize - idle,
+                max,
+                acquire_latency_ms: ping.ok().map(|latency| latency.as_secs_f64() * 1000.0),
+            },
+        }),
+    )
+}
+
+/// Whether `path` matches the OpenAPI path `pattern`, where a `{param}` segment matches any
+/// segment
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut patterns = pattern.split('/');
+    let mut segments = path.split('/');
+    loop {
+        match (patterns.next(), segments.next()) {
+            (None, None) => return true,
+            (Some(pattern), Some(segment))
+                if pattern == segment || (pattern.starts_with('{') && !segment.is_empty()) => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Rejects write requests while the service is in read-only mode
+///
+/// `POST` operations tagged `Reading` are still served, at their `reading_paths`.
+struct ReadOnly {
+    reading_paths: Arc<Vec<String>>,
+}
+
+impl<E: Endpoint> Middleware<E> for ReadOnly {
+    type Output = ReadOnlyEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ReadOnlyEndpoint {
+            ep,
+            reading_paths: self.reading_paths.clone(),
+        }
+    }
+}
+
+struct ReadOnlyEndpoint<E> {
+    ep: E,
+    reading_paths: Arc<Vec<String>>,
+}
+
+#[poem::async_trait]
+impl<E: Endpoint> Endpoint for ReadOnlyEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let reading = *req.method() == Method::POST
+            && self
+                .reading_paths
+                .iter()
+                .any(|pattern| path_matches(pattern, req.uri().path()));
+        if !reading
+            && matches!(
+                *req.method(),
+                Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+            )
+        {
+            return Ok(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body("The service is in read-only mode, write operations are unavailable."));
+        }
+
+        self.ep.call(req).await.map(IntoResponse::into_response)
+    }
+}
+
+/// Serves `GET` requests from a read replica if there is one, unless they set the
+/// `X-Read-Primary` header to read their own writes
+struct ReadReplica {
+    pool: Option<PgPool>,
+}
+
+impl<E: Endpoint> Middleware<E> for ReadReplica {
+    type Output = ReadReplicaEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ReadReplicaEndpoint {
+            ep,
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+struct ReadReplicaEndpoint<E> {
+    ep: E,
+    pool: Option<PgPool>,
+}
+
+#[poem::async_trait]
+impl<E: Endpoint> Endpoint for ReadReplicaEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let read_primary = req.header("x-read-primary").is_some_and(parse_flag);
+        if let Some(pool) = self.pool.as_ref().filter(|_| req.method() == Method::GET) {
+            if !read_primary {
+                // Replaces the primary pool the handlers get through `Data<&PgPool>`
+                req.extensions_mut().insert(pool.clone());
+            }
+        }
+
+        self.ep.call(req).await
+    }
+}
+
+/// The body of an error response with `status` and `message`, in the shape of the envelope
+fn error_body(status: StatusCode, message: &str) -> serde_json::Value {
+    serde_json::json!({
+        "error": {
+            "code": status.as_u16(),
+            "message": message,
+        }
+    })
+}
+
+/// Answers requests with a malformed id in their path with a JSON `400 Bad Request` naming it,
+/// instead of the plain error of the framework
+struct InvalidPathIds {
+    /// The segments of the API's paths that aren't parameters
+    static_segments: Arc<HashSet<String>>,
+}
+
+impl<E: Endpoint> Middleware<E> for InvalidPathIds {
+    type Output = InvalidPathIdsEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        InvalidPathIdsEndpoint {
+            ep,
+            static_segments: self.static_segments.clone(),
+        }
+    }
+}
+
+struct InvalidPathIdsEndpoint<E> {
+    ep: E,
+    static_segments: Arc<HashSet<String>>,
+}
+
+#[poem::async_trait]
+impl<E: Endpoint> Endpoint for InvalidPathIdsEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let path = req.uri().path().to_string();
+
+        match self.ep.call(req).await {
+            Ok(resp) => Ok(resp.into_response()),
+            Err(err) if err.is::<ParsePathError>() => {
+                let id = path
+                    .split('/')
+                    .find(|segment| !segment.is_empty() && !self.static_segments.contains(*segment))
+                    .unwrap_or_default();
+                let message = format!("invalid id '{id}', expected integer");
+
+                Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .content_type("application/json; charset=utf-8")
+                    .body(error_body(StatusCode::BAD_REQUEST, &message).to_string()))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Wraps responses in a consistent envelope, either by default or when requested through the
+/// `envelope` query parameter
+///
+/// Successful responses become `{"data": ..., "meta": {...}}` and errors become
+/// `{"error": {"code": ..., "message": ...}}`.
+struct Envelope {
+    default: bool,
+}
+
+impl<E: Endpoint> Middleware<E> for Envelope {
+    type Output = EnvelopeEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        EnvelopeEndpoint {
+            ep,
+            default: self.default,
+        }
+    }
+}
+
+struct EnvelopeEndpoint<E> {
+    ep: E,
+    default: bool,
+}
+
+#[poem::async_trait]
+impl<E: Endpoint> Endpoint for EnvelopeEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let enabled = req
+            .uri()
+            .query()
+            .and_then(|query| {
+                query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("envelope="))
+            })
+            .map_or(self.default, parse_flag);
+        let mut resp = match self.ep.call(req).await {
+            Ok(resp) => resp.into_response(),
+            Err(err) => err.into_response(),
+        };
+
+        let is_event_stream = resp
+            .content_type()
+            .is_some_and(|content_type| content_type.starts_with("text/event-stream"));
+        if !enabled || resp.status() == StatusCode::NO_CONTENT || is_event_stream {
+            return Ok(resp);
+        }
+
+        let status = resp.status();
+        let is_json = resp
+            .content_type()
+            .is_some_and(|content_type| content_type.starts_with("application/json"));
+        if !status.is_success() && is_json {
+            // Already shaped like an enveloped error
+            return Ok(resp);
+        }
+
+        let body = resp.take_body().into_bytes().await?;
+        let envelope = if status.is_success() {
+            let data = serde_json::from_slice(&body).unwrap_or_else(|_| {
+                serde_json::Value::String(String::from_utf8_lossy(&body).into_owned())
+            });
+            let meta = match &data {
+                serde_json::Value::Array(items) => serde_json::json!({ "count": items.len() }),
+                _ => serde_json::json!({}),
+            };
+
+            serde_json::json!({ "data": data, "meta": meta })
+        } else {
+            error_body(status, &String::from_utf8_lossy(&body))
+        };
+
+        resp.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json; charset=utf-8"),
+        );
+        resp.set_body(envelope.to_string());
+
+        Ok(resp)
+    }
+}
+
+/// Applies the stock change recorded by `entry` to the stock items it references, returning the
+/// changed stock items
+///
+/// The inner `Err` holds the reason the change can't be applied, in which case the transaction
+/// should be rolled back.
+async fn apply_stock_entry(
+    tx: &mut Transaction<'_, Postgres>,
+    entry: &StockEntry,
+) -> Result<std::result::Result<Vec<StockChange>, String>> {
+    let Some(stock_item_id) = entry.stock_item_id else {
+        return Ok(Err(format!(
+            "Stock entry '{}' doesn't reference a stock item.",
+            entry.id
+        )));
+    };
+    let delta = match entry.entry_type {
+        EntryType::Purchase | EntryType::Adjust => entry.stock_quantity,
+        EntryType::Transfer | EntryType::Consume | EntryType::Expire => -entry.stock_quantity,
+    };
+
+    let item = sqlx::query!(
+        r#"
+UPDATE stock_items
+SET stock_quantity = stock_quantity + $2
+WHERE id = $1
+RETURNING product_id, space_id, stock_quantity, best_by_date"#,
+        stock_item_id,
+        delta
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+
+    let Some(item) = item else {
+        return Ok(Err(format!(
+            "No stock item with id '{stock_item_id}' found."
+        )));
+    };
+    if item.stock_quantity < 0.0 {
+        return Ok(Err(format!(
+            "Insufficient stock in stock item '{stock_item_id}' (short by {}).",
+            -item.stock_quantity
+        )));
+    }
+    let mut changes = vec![StockChange {
+        stock_item_id,
+        product_id: item.product_id,
+        space_id: item.space_id,
+        stock_quantity: item.stock_quantity,
+    }];
+
+    if let EntryType::Transfer = entry.entry_type {
+        let Some(to_space_id) = entry.to_space_id else {
+            return Ok(Err(format!(
+                "Transfer entry '{}' doesn't reference a space to transfer to.",
+                entry.id
+            )));
+        };
+
+        let to_item = sqlx::query!(
+            r#"
+INSERT INTO stock_items (product_id, space_id, stock_quantity, best_by_date)
+VALUES ($1, $2, $3, $4)
+ON CONFLICT (product_id, space_id, best_by_date)
+DO UPDATE SET stock_quantity = stock_items.stock_quantity + EXCLUDED.stock_quantity
+RETURNING id, stock_quantity"#,
+            item.product_id,
+            to_space_id,
+            entry.stock_quantity,
+            item.best_by_date
+        )
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(InternalServerError)?;
+        changes.push(StockChange {
+            stock_item_id: to_item.id,
+            product_id: item.product_id,
+            space_id: to_space_id,
+            stock_quantity: to_item.stock_quantity,
+        });
+    }
+
+    Ok(Ok(changes))
+}
+
+/// A stock entry to record through `record_stock_entry`
+struct EntryDraft {
+    entry_type: EntryType,
+    stock_quantity: f32,
+    stock_item_id: i32,
+    product_id: Option<i32>,
+    place_id: Option<i32>,
+    to_space_id: Option<i32>,
+    price: Option<f32>,
+    memo: Option<String>,
+}
+
+/// Records `draft` as a confirmed stock entry within `tx` and applies its change to stock,
+/// returning the recorded entry and the changed stock items
+///
+/// The inner `Err` holds why the entry can't be recorded, in which case the transaction should
+/// be rolled back.
+async fn record_stock_entry(
+    tx: &mut Transaction<'_, Postgres>,
+    draft: EntryDraft,
+    max_memo_length: usize,
+) -> Result<std::result::Result<(StockEntry, Vec<StockChange>), RecordError>> {
+    let memo = match normalize_memo(draft.memo.as_deref(), max_memo_length) {
+        Ok(memo) => memo,
+        Err(reason) => return Ok(Err(RecordError::BadRequest(reason))),
+    };
+    let entry = sqlx::query_as!(
+        StockEntry,
+        r#"
+INSERT INTO stock_entries (entry_type, stock_quantity, stock_item_id, product_id, place_id,
+    to_space_id, price, memo)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+RETURNING id, entry_timestamp, entry_type AS "entry_type: EntryType", stock_quantity,
+    stock_item_id, product_id, place_id, to_space_id, price, memo,
+    status AS "status: EntryStatus""#,
+        draft.entry_type as EntryType,
+        draft.stock_quantity,
+        draft.stock_item_id,
+        draft.product_id,
+        draft.place_id,
+        draft.to_space_id,
+        draft.price,
+        memo
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+
+    Ok(apply_stock_entry(tx, &entry)
+        .await?
+        .map(|changes| (entry, changes))
+        .map_err(RecordError::Unprocessable))
+}
+
+/// Trims `memo`, dropping it when blank
+///
+/// Fails when the trimmed memo is longer than `max_length` characters.
+fn normalize_memo(
+    memo: Option<&str>,
+    max_length: usize,
+) -> std::result::Result<Option<String>, String> {
+    match memo.map(str::trim).filter(|memo| !memo.is_empty()) {
+        Some(memo) if memo.chars().count() > max_length => Err(format!(
+            "Memo is longer than the maximum of {max_length} characters."
+        )),
+        memo => Ok(memo.map(str::to_string)),
+    }
+}
+
+/// Confirms pending stock entry `id` within `tx`, applying its change to stock
+///
+/// Returns the confirmed entry and the stock items it changed.
+async fn confirm_pending_entry(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+) -> Result<std::result::Result<(StockEntry, Vec<StockChange>), ConfirmError>> {
+    let entry = sqlx::query_as!(
+        StockEntry,
+        r#"
+SELECT id, entry_timestamp, entry_type AS "entry_type: EntryType", stock_quantity,
+    stock_item_id, product_id, place_id, to_space_id, price, memo,
+    status AS "status: EntryStatus"
+FROM stock_entries
+WHERE id = $1
+FOR UPDATE"#,
+        id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+
+    let Some(mut entry) = entry else {
+        return Ok(Err(ConfirmError::NotFound(format!(
+            "No stock entry with id '{id}' found."
+        ))));
+    };
+    if entry.status == EntryStatus::Confirmed {
+        return Ok(Err(ConfirmError::Conflict(format!(
+            "Stock entry '{id}' is already confirmed."
+        ))));
+    }
+    let changes = match apply_stock_entry(tx, &entry).await? {
+        Ok(changes) => changes,
+        Err(reason) => return Ok(Err(ConfirmError::Unprocessable(reason))),
+    };
+
+    sqlx::query!(
+        "UPDATE stock_entries SET status = 'confirmed' WHERE id = $1",
+        id
+    )
+    .execute(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+    entry.status = EntryStatus::Confirmed;
+
+    Ok(Ok((entry, changes)))
+}
+
+/// Archives or restores product `id`
+async fn set_product_archived(
+    pool: &PgPool,
+    id: i32,
+    archived: bool,
+) -> Result<UpdateResponse<Product>> {
+    let product = sqlx::query_as!(
+        Product,
+        r#"
+UPDATE products
+SET archived = $2
+WHERE id = $1
+RETURNING id, name, description, parent_product_id, purchase_unit_id, stock_unit_id,
+    purchase_to_stock_factor, min_stock, max_stock, barcode, archived,
+    depletion_strategy AS "depletion_strategy: DepletionStrategy""#,
+        id,
+        archived
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(InternalServerError)?;
+
+    match product {
+        Some(product) => Ok(UpdateResponse::Success(Json(product))),
+        None => Ok(UpdateResponse::NotFound(PlainText(format!(
+            "No product with id '{id}' found."
+        )))),
+    }
+}
+
+/// Replaces the fields of product `id` with those of `product` within `tx`, except whether it's
+/// archived
+async fn update_product(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+    product: &Product,
+) -> Result<EditResponse<Product>> {
+    if let (Some(min_stock), Some(max_stock)) = (product.min_stock, product.max_stock) {
+        if min_stock > max_stock {
+            return Ok(EditResponse::Unprocessable(PlainText(format!(
+                "Minimum stock '{min_stock}' is greater than maximum stock '{max_stock}'."
+            ))));
+        }
+    }
+
+    let result = sqlx::query_as!(
+        Product,
+        r#"
+UPDATE products
+SET name = $2, description = $3, parent_product_id = $4, purchase_unit_id = $5,
+    stock_unit_id = $6, purchase_to_stock_factor = $7, min_stock = $8, max_stock = $9,
+    barcode = $10, depletion_strategy = $11
+WHERE id = $1
+RETURNING id, name, description, parent_product_id, purchase_unit_id, stock_unit_id,
+    purchase_to_stock_factor, min_stock, max_stock, barcode, archived,
+    depletion_strategy AS "depletion_strategy: DepletionStrategy""#,
+        id,
+        product.name,
+        product.description,
+        product.parent_product_id,
+        product.purchase_unit_id,
+        product.stock_unit_id,
+        product.purchase_to_stock_factor,
+        product.min_stock,
+        product.max_stock,
+        product.barcode,
+        product.depletion_strategy as DepletionStrategy
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    match result {
+        Ok(Some(product)) => Ok(EditResponse::Success(Json(product))),
+        Ok(None) => Ok(EditResponse::NotFound(PlainText(format!(
+            "No product with id '{id}' found."
+        )))),
+        Err(err) if is_unique_violation(&err) => Ok(EditResponse::Conflict(PlainText(format!(
+            "A product with barcode '{}' already exists.",
+            product.barcode.as_deref().unwrap_or_default()
+        )))),
+        Err(err) => Err(InternalServerError(err)),
+    }
+}
+
+/// Replaces the fields of unit `id` with those of `unit` within `tx`, except whether it's archived
+async fn update_unit(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+    unit: &Unit,
+) -> Result<EditResponse<Unit>> {
+    let result = sqlx::query_as!(
+        Unit,
+        r#"
+UPDATE units
+SET singular = $2, plural = $3, decimal_places = $4
+WHERE id = $1
+RETURNING *"#,
+        id,
+        unit.singular,
+        unit.plural,
+        unit.decimal_places
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    match result {
+        Ok(Some(unit)) => Ok(EditResponse::Success(Json(unit))),
+        Ok(None) => Ok(EditResponse::NotFound(PlainText(format!(
+            "No unit with id '{id}' found."
+        )))),
+        Err(err) if is_unique_violation(&err) => Ok(EditResponse::Conflict(PlainText(format!(
+            "A unit '{}' already exists.",
+            unit.singular
+        )))),
+        Err(err) => Err(InternalServerError(err)),
+    }
+}
+
+/// Replaces the fields of place `id` with those of `place` within `tx`
+async fn update_place(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+    place: &Place,
+) -> Result<UpdateResponse<Place>> {
+    let result = sqlx::query_as!(
+        Place,
+        r#"
+UPDATE places
+SET name = $2, description = $3
+WHERE id = $1
+RETURNING *"#,
+        id,
+        place.name,
+        place.description
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+
+    match result {
+        Some(place) => Ok(UpdateResponse::Success(Json(place))),
+        None => Ok(UpdateResponse::NotFound(PlainText(format!(
+            "No place with id '{id}' found."
+        )))),
+    }
+}
+
+/// Replaces the fields of space `id` with those of `space` within `tx`
+async fn update_space(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+    space: &Space,
+) -> Result<UpdateResponse<Space>> {
+    let result = sqlx::query_as!(
+        Space,
+        r#"
+UPDATE spaces
+SET name = $2, description = $3
+WHERE id = $1
+RETURNING *"#,
+        id,
+        space.name,
+        space.description
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+
+    match result {
+        Some(space) => Ok(UpdateResponse::Success(Json(space))),
+        None => Ok(UpdateResponse::NotFound(PlainText(format!(
+            "No space with id '{id}' found."
+        )))),
+    }
+}
+
+/// Replaces the units and factor of (not deleted) unit conversion `id` with those of `conversion`
+/// within `tx`, checking them like a new conversion
+///
+/// The conversion is marked as deleted while it's checked, so that it doesn't conflict with
+/// itself. `tx` should be rolled back unless the update succeeded.
+async fn update_unit_conversion(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+    conversion: &UnitConversion,
+) -> Result<EditResponse<UnitConversion>> {
+    if conversion.from_unit_id == conversion.to_unit_id {
+        return Ok(EditResponse::BadRequest(PlainText(format!(
+            "Can't convert unit '{}' to itself.",
+            conversion.from_unit_id
+        ))));
+    }
+    let hidden = sqlx::query!(
+        r#"
+UPDATE unit_conversions
+SET deleted_at = LOCALTIMESTAMP
+WHERE id = $1 AND deleted_at IS NULL
+RETURNING id"#,
+        id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+    if hidden.is_none() {
+        return Ok(EditResponse::NotFound(PlainText(format!(
+            "No unit conversion with id '{id}' found."
+        ))));
+    }
+
+    if let Some(existing_id) =
+        existing_unit_conversion(&mut **tx, conversion.from_unit_id, conversion.to_unit_id).await?
+    {
+        return Ok(EditResponse::Conflict(PlainText(format!(
+            "Unit conversion '{existing_id}' already converts unit '{}' to unit '{}'.",
+            conversion.from_unit_id, conversion.to_unit_id
+        ))));
+    }
+    if let Some(contradicting_id) = contradicting_unit_conversion(
+        &mut **tx,
+        conversion.from_unit_id,
+        conversion.to_unit_id,
+        conversion.factor,
+    )
+    .await?
+    {
+        return Ok(EditResponse::Conflict(PlainText(format!(
+            "Unit conversion '{contradicting_id}' converts unit '{}' to unit '{}' with a contradicting factor.",
+            conversion.to_unit_id, conversion.from_unit_id
+        ))));
+    }
+    if let Some(reason) = inconsistent_cycle(
+        &mut **tx,
+        conversion.from_unit_id,
+        conversion.to_unit_id,
+        conversion.factor,
+    )
+    .await?
+    {
+        return Ok(EditResponse::Unprocessable(PlainText(reason)));
+    }
+
+    let conversion = sqlx::query_as!(
+        UnitConversion,
+        r#"
+UPDATE unit_conversions
+SET from_unit_id = $2, to_unit_id = $3, factor = $4, deleted_at = NULL
+WHERE id = $1
+RETURNING *"#,
+        id,
+        conversion.from_unit_id,
+        conversion.to_unit_id,
+        conversion.factor
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+
+    Ok(EditResponse::Success(Json(conversion)))
+}
+
+/// Replaces the fields of stock item `id` with those of `item` within `tx`
+///
+/// A changed quantity is recorded as an `Adjust` entry for the difference.
+async fn update_stock_item(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+    item: &StockItem,
+) -> Result<EditResponse<StockItem>> {
+    if let Some(reason) =
+        check_discrete_quantity(&mut **tx, item.product_id, item.stock_quantity).await?
+    {
+        return Ok(EditResponse::Unprocessable(PlainText(reason)));
+    }
+
+    let result = sqlx::query!(
+        r#"
+UPDATE stock_items si
+SET product_id = $2, space_id = $3, stock_quantity = $4, best_by_date = $5
+FROM (SELECT id, stock_quantity FROM stock_items WHERE id = $1 FOR UPDATE) old
+WHERE si.id = old.id
+RETURNING si.id, si.product_id, si.space_id, si.stock_quantity, si.best_by_date,
+    old.stock_quantity AS previous_quantity"#,
+        id,
+        item.product_id,
+        item.space_id,
+        item.stock_quantity,
+        item.best_by_date
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    match result {
+        Ok(Some(record)) => {
+            let delta = record.stock_quantity - record.previous_quantity;
+            if delta != 0.0 {
+                sqlx::query!(
+                    r#"
+INSERT INTO stock_entries (entry_type, stock_quantity, stock_item_id, product_id, memo)
+VALUES ('adjust', $1, $2, $3, $4)"#,
+                    delta,
+                    id,
+                    record.product_id,
+                    format!(
+                        "Set stock quantity from {} to {}",
+                        record.previous_quantity, record.stock_quantity
+                    )
+                )
+                .execute(&mut **tx)
+                .await
+                .map_err(InternalServerError)?;
+            }
+
+            Ok(EditResponse::Success(Json(StockItem {
+                id: record.id.into(),
+                product_id: record.product_id,
+                space_id: record.space_id,
+                stock_quantity: record.stock_quantity,
+                best_by_date: record.best_by_date,
+            })))
+        }
+        Ok(None) => Ok(EditResponse::NotFound(PlainText(format!(
+            "No stock item with id '{id}' found."
+        )))),
+        Err(err) if is_unique_violation(&err) => Ok(EditResponse::Conflict(PlainText(format!(
+            "A stock item of product '{}' in space '{}' with the same best by date already exists.",
+            item.product_id, item.space_id
+        )))),
+        Err(err) => Err(InternalServerError(err)),
+    }
+}
+
+/// Checks `entry` against the business rules, returning every rule it breaks
+async fn validate_stock_entry(
+    pool: &PgPool,
+    entry: &StockEntry,
+    max_memo_length: usize,
+) -> Result<Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let mut error = |field: &str, message: String| {
+        errors.push(FieldError {
+            field: field.to_string(),
+            message,
+        })
+    };
+
+    let (required, extraneous): (&[&str], &[&str]) = match entry.entry_type {
+        EntryType::Purchase => (&["stock_item_id"], &["to_space_id"]),
+        EntryType::Transfer => (&["stock_item_id", "to_space_id"], &["place_id", "price"]),
+        EntryType::Consume | EntryType::Expire | EntryType::Adjust => {
+            (&["stock_item_id"], &["to_space_id", "place_id", "price"])
+        }
+    };
+    let fields = [
+        ("stock_item_id", entry.stock_item_id.is_some()),
+        ("to_space_id", entry.to_space_id.is_some()),
+        ("place_id", entry.place_id.is_some()),
+        ("price", entry.price.is_some()),
+    ];
+    for (field, is_set) in fields {
+        if !is_set && required.contains(&field) {
+            error(
+                field,
+                format!("`{field}` is required for a {:?} entry.", entry.entry_type),
+            );
+        } else if is_set && extraneous.contains(&field) {
+            error(
+                field,
+                format!(
+                    "`{field}` must not be set for a {:?} entry.",
+                    entry.entry_type
+                ),
+            );
+        }
+    }
+
+    if !entry.stock_quantity.is_finite() {
+        error(
+            "stock_quantity",
+            format!(
+                "Stock quantity '{}' isn't a finite number.",
+                entry.stock_quantity
+            ),
+        );
+    } else if let EntryType::Adjust = entry.entry_type {
+        if entry.stock_quantity == 0.0 {
+            error(
+                "stock_quantity",
+                "Stock quantity of an adjustment must not be zero.".to_string(),
+            );
+        }
+    } else if entry.stock_quantity <= 0.0 {
+        error(
+            "stock_quantity",
+            format!(
+                "Stock quantity '{}' must be positive.",
+                entry.stock_quantity
+            ),
+        );
+    }
+    if let Some(price) = entry.price {
+        if !price.is_finite() || price < 0.0 {
+            error(
+                "price",
+                format!("Price '{price}' must be a finite, non-negative number."),
+            );
+        }
+    }
+    if let Err(reason) = normalize_memo(entry.memo.as_deref(), max_memo_length) {
+        error("memo", reason);
+    }
+
+    let record = sqlx::query!(
+        r#"
+SELECT EXISTS (SELECT 1 FROM products WHERE id = $1) AS "product_exists!",
+    EXISTS (SELECT 1 FROM places WHERE id = $2) AS "place_exists!",
+    EXISTS (SELECT 1 FROM spaces WHERE id = $3) AS "to_space_exists!",
+    si.product_id AS "item_product_id?",
+    si.space_id AS "item_space_id?",
+    si.stock_quantity AS "item_quantity?"
+FROM (SELECT 1) AS entry
+LEFT JOIN stock_items si ON si.id = $4"#,
+        entry.product_id,
+        entry.place_id,
+        entry.to_space_id,
+        entry.stock_item_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(InternalServerError)?;
+
+    if let Some(product_id) = entry.product_id {
+        if !record.product_exists {
+            error(
+                "product_id",
+                format!("No product with id '{product_id}' found."),
+            );
+        }
+    }
+    if let Some(place_id) = entry.place_id {
+        if !record.place_exists {
+            error("place_id", format!("No place with id '{place_id}' found."));
+        }
+    }
+    if let Some(to_space_id) = entry.to_space_id {
+        if !record.to_space_exists {
+            error(
+                "to_space_id",
+                format!("No space with id '{to_space_id}' found."),
+            );
+        }
+    }
+    if let Some(stock_item_id) = entry.stock_item_id {
+        match (
+            record.item_product_id,
+            record.item_space_id,
+            record.item_quantity,
+        ) {
+            (Some(item_product_id), Some(item_space_id), Some(item_quantity)) => {
+                if entry
+                    .product_id
+                    .is_some_and(|product_id| product_id != item_product_id)
+                {
+                    error(
+                        "product_id",
+                        format!("Stock item '{stock_item_id}' holds product '{item_product_id}'."),
+                    );
+                }
+                if let EntryType::Transfer = entry.entry_type {
+                    if entry.to_space_id == Some(item_space_id) {
+                        error(
+                            "to_space_id",
+                            format!("Stock item '{stock_item_id}' is already in space '{item_space_id}'."),
+                        );
+                    }
+                }
+                let withdraws = matches!(
+                    entry.entry_type,
+                    EntryType::Transfer | EntryType::Consume | EntryType::Expire
+                );
+                if withdraws && entry.stock_quantity > item_quantity {
+                    error(
+                        "stock_quantity",
+                        format!(
+                            "Insufficient stock in stock item '{stock_item_id}' (short by {}).",
+                            entry.stock_quantity - item_quantity
+                        ),
+                    );
+                }
+            }
+            _ => error(
+                "stock_item_id",
+                format!("No stock item with id '{stock_item_id}' found."),
+            ),
+        }
+    }
+
+    // The stock item's product is the one whose stock changes, even when `product_id` differs
+    let product_id = record.item_product_id.or(entry.product_id);
+    if let Some(product_id) = product_id.filter(|_| entry.stock_quantity.is_finite()) {
+        if let Some(reason) =
+            check_discrete_quantity(pool, product_id, entry.stock_quantity).await?
+        {
+            error("stock_quantity", reason);
+        }
+    }
+
+    Ok(errors)
+}
+
+/// Embeds the `expand`ed relations of each of the stock `items`
+async fn expand_stock_items(
+    pool: &PgPool,
+    items: Vec<StockItem>,
+    expand: &[StockItemRelation],
+) -> Result<Vec<ExpandedStockItem>> {
+    if expand.is_empty() {
+        return Ok(items
+            .into_iter()
+            .map(|item| ExpandedStockItem {
+                item,
+                expanded: None,
+            })
+            .collect());
+    }
+
+    let mut products = HashMap::new();
+    if expand.contains(&StockItemRelation::Product) {
+        let ids: Vec<i32> = items.iter().map(|item| item.product_id).collect();
+        products = sqlx::query_as!(
+            Product,
+            r#"
+SELECT id, name, description, parent_product_id, purchase_unit_id, stock_unit_id,
+    purchase_to_stock_factor, min_stock, max_stock, barcode, archived,
+    depletion_strategy AS "depletion_strategy: DepletionStrategy"
+FROM products
+WHERE id = ANY($1)"#,
+            &ids
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(InternalServerError)?
+        .into_iter()
+        .map(|product| (product.id, product))
+        .collect();
+    }
+    let mut spaces = HashMap::new();
+    if expand.contains(&StockItemRelation::Space) {
+        let ids: Vec<i32> = items.iter().map(|item| item.space_id).collect();
+        spaces = sqlx::query_as!(Space, "SELECT * FROM spaces WHERE id = ANY($1)", &ids)
+            .fetch_all(pool)
+            .await
+            .map_err(InternalServerError)?
+            .into_iter()
+            .map(|space| (space.id, space))
+            .collect();
+    }
+
+    Ok(items
+        .into_iter()
+        .map(|item| ExpandedStockItem {
+            expanded: Some(StockItemRelations {
+                product: products.get(&i64::from(item.product_id)).cloned(),
+                space: spaces.get(&i64::from(item.space_id)).cloned(),
+            }),
+            item,
+        })
+        .collect())
+}
+
+/// Replaces the `"$<index>.id"` references in `value` by the id created by that operation, of
+/// the `ids` created so far
+fn resolve_references(
+    value: &mut serde_json::Value,
+    ids: &[i32],
+) -> std::result::Result<(), String> {
+    match value {
+        serde_json::Value::String(reference) if reference.starts_with('$') => {
+            let id = reference
+                .strip_prefix('$')
+                .and_then(|reference| reference.strip_suffix(".id"))
+                .and_then(|index| index.parse::<usize>().ok())
+                .and_then(|index| ids.get(index))
+                .ok_or_else(|| {
+                    format!("Reference '{reference}' doesn't name an earlier operation.")
+                })?;
+            *value = serde_json::Value::from(*id);
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                resolve_references(value, ids)?;
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for value in fields.values_mut() {
+                resolve_references(value, ids)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// The id of the (not deleted) unit conversion from unit `from_unit_id` to unit `to_unit_id`, if
+/// there is one
+async fn existing_unit_conversion(
+    executor: impl PgExecutor<'_>,
+    from_unit_id: i32,
+    to_unit_id: i32,
+) -> Result<Option<i32>> {
+    sqlx::query_scalar!(
+        r#"
+SELECT id
+FROM unit_conversions
+WHERE from_unit_id = $1 AND to_unit_id = $2 AND deleted_at IS NULL"#,
+        from_unit_id,
+        to_unit_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(InternalServerError)
+}
+
+/// The id of a (not deleted) unit conversion from unit `to_unit_id` back to unit `from_unit_id`
+/// whose factor contradicts `factor`, if there is one
+async fn contradicting_unit_conversion(
+    executor: impl PgExecutor<'_>,
+    from_unit_id: i32,
+    to_unit_id: i32,
+    factor: Option<f32>,
+) -> Result<Option<i32>> {
+    let Some(factor) = factor.filter(|&factor| factor != 0.0) else {
+        return Ok(None);
+    };
+    let reverse = sqlx::query!(
+        r#"
+SELECT id, factor AS "factor!"
+FROM unit_conversions
+WHERE from_unit_id = $1 AND to_unit_id = $2 AND deleted_at IS NULL
+    AND factor IS NOT NULL AND factor <> 0"#,
+        to_unit_id,
+        from_unit_id
+    )
+    .fetch_all(executor)
+    .await
+    .map_err(InternalServerError)?;
+
+    Ok(reverse
+        .into_iter()
+        .find(|conversion| {
+            factors_conflict(
+                f64::from(factor),
+                1.0 / f64::from(conversion.factor),
+                DEFAULT_FACTOR_TOLERANCE,
+            )
+        })
+        .map(|conversion| conversion.id))
+}
+
+/// Why adding a conversion from unit `from_unit_id` to unit `to_unit_id` with `factor` would make
+/// the conversions inconsistent, if it would
+///
+/// A new conversion between units the existing conversions already connect closes a cycle, and
+/// its factor has to match the one implied by the conversions around it.
+async fn inconsistent_cycle(
+    executor: impl PgExecutor<'_>,
+    from_unit_id: i32,
+    to_unit_id: i32,
+    factor: Option<f32>,
+) -> Result<Option<String>> {
+    let Some(factor) = factor.filter(|&factor| factor != 0.0) else {
+        return Ok(None);
+    };
+    let graph = ConversionGraph::load(executor, usize::MAX)
+        .await
+        .map_err(InternalServerError)?;
+
+    Ok(graph
+        .implied_factor(from_unit_id, to_unit_id)
+        .filter(|&implied| factors_conflict(f64::from(factor), implied, DEFAULT_FACTOR_TOLERANCE))
+        .map(|implied| {
+            format!(
+                "Factor '{factor}' from unit '{from_unit_id}' to unit '{to_unit_id}' is inconsistent with the existing conversions, which imply '{implied}'."
+            )
+        }))
+}
+
+/// Parses a batch operation's `body` as the payload of its endpoint
+fn parse_body<T: ParseFromJSON>(body: serde_json::Value) -> std::result::Result<T, OperationError> {
+    T::parse_from_json(Some(body)).map_err(|err| OperationError::Unprocessable(err.into_message()))
+}
+
+/// Runs the creation of batch operation `operation` within `tx`, returning the created id and,
+/// for a stock item, its stock change
+async fn run_batch_operation(
+    tx: &mut Transaction<'_, Postgres>,
+    operation: BatchOperation,
+) -> Result<std::result::Result<(i32, Option<StockChange>), OperationError>> {
+    let mut change = None;
+    let result = match operation.path.as_str() {
+        "/products" => {
+            let product: Product = match parse_body(operation.body) {
+                Ok(product) => product,
+                Err(err) => return Ok(Err(err)),
+            };
+            if let (Some(min_stock), Some(max_stock)) = (product.min_stock, product.max_stock) {
+                if min_stock > max_stock {
+                    return Ok(Err(OperationError::Unprocessable(format!(
+                        "Minimum stock '{min_stock}' is greater than maximum stock '{max_stock}'."
+                    ))));
+                }
+            }
+
+            sqlx::query_scalar!(
+                r#"
+INSERT INTO products (name, description, parent_product_id, purchase_unit_id, stock_unit_id, purchase_to_stock_factor, min_stock, max_stock, barcode, depletion_strategy)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+RETURNING id"#,
+                product.name,
+                product.description,
+                product.parent_product_id,
+                product.purchase_unit_id,
+                product.stock_unit_id,
+                product.purchase_to_stock_factor,
+                product.min_stock,
+                product.max_stock,
+                product.barcode,
+                product.depletion_strategy as DepletionStrategy
+            )
+            .fetch_one(&mut **tx)
+            .await
+        }
+        "/units" => {
+            let unit: Unit = match parse_body(operation.body) {
+                Ok(unit) => unit,
+                Err(err) => return Ok(Err(err)),
+            };
+
+            sqlx::query_scalar!(
+                r#"
+INSERT INTO units (singular, plural, decimal_places)
+VALUES ($1, $2, $3)
+RETURNING id"#,
+                unit.singular,
+                unit.plural,
+                unit.decimal_places,
+            )
+            .fetch_one(&mut **tx)
+            .await
+        }
+        "/unit_conversions" => {
+            let conversion: UnitConversion = match parse_body(operation.body) {
+                Ok(conversion) => conversion,
+                Err(err) => return Ok(Err(err)),
+            };
+            if conversion.from_unit_id == conversion.to_unit_id {
+                return Ok(Err(OperationError::Unprocessable(format!(
+                    "Can't convert unit '{}' to itself.",
+                    conversion.from_unit_id
+                ))));
+            }
+            if let Some(id) =
+                existing_unit_conversion(&mut **tx, conversion.from_unit_id, conversion.to_unit_id)
+                    .await?
+            {
+                return Ok(Err(OperationError::Conflict(format!(
+                    "Unit conversion '{id}' already converts unit '{}' to unit '{}'.",
+                    conversion.from_unit_id, conversion.to_unit_id
+                ))));
+            }
+            if let Some(id) = contradicting_unit_conversion(
+                &mut **tx,
+                conversion.from_unit_id,
+                conversion.to_unit_id,
+                conversion.factor,
+            )
+            .await?
+            {
+                return Ok(Err(OperationError::Conflict(format!(
+                    "Unit conversion '{id}' converts unit '{}' to unit '{}' with a contradicting factor.",
+                    conversion.to_unit_id, conversion.from_unit_id
+                ))));
+            }
+            if let Some(reason) = inconsistent_cycle(
+                &mut **tx,
+                conversion.from_unit_id,
+                conversion.to_unit_id,
+                conversion.factor,
+            )
+            .await?
+            {
+                return Ok(Err(OperationError::Unprocessable(reason)));
+            }
+
+            sqlx::query_scalar!(
+                r#"
+INSERT INTO unit_conversions (from_unit_id, to_unit_id, factor)
+VALUES ($1, $2, $3)
+RETURNING id"#,
+                conversion.from_unit_id,
+                conversion.to_unit_id,
+                conversion.factor,
+            )
+            .fetch_one(&mut **tx)
+            .await
+        }
+        "/place" => {
+            let place: Place = match parse_body(operation.body) {
+                Ok(place) => place,
+                Err(err) => return Ok(Err(err)),
+            };
+
+            sqlx::query_scalar!(
+                r#"
+INSERT INTO places (name, description)
+VALUES ($1, $2)
+RETURNING id"#,
+                place.name,
+                place.description,
+            )
+            .fetch_one(&mut **tx)
+            .await
+        }
+        "/space" => {
+            let space: Space = match parse_body(operation.body) {
+                Ok(space) => space,
+                Err(err) => return Ok(Err(err)),
+            };
+
+            sqlx::query_scalar!(
+                r#"
+INSERT INTO spaces (name, description)
+VALUES ($1, $2)
+RETURNING id"#,
+                space.name,
+                space.description,
+            )
+            .fetch_one(&mut **tx)
+            .await
+        }
+        "/stock_item" => {
+            let item: StockItem = match parse_body(operation.body) {
+                Ok(item) => item,
+                Err(err) => return Ok(Err(err)),
+            };
+            if let Some(reason) =
+                check_discrete_quantity(&mut **tx, item.product_id, item.stock_quantity).await?
+            {
+                return Ok(Err(OperationError::Unprocessable(reason)));
+            }
+
+            sqlx::query_scalar!(
+                r#"
+INSERT INTO stock_items (product_id, space_id, stock_quantity, best_by_date)
+VALUES ($1, $2, $3, $4)
+RETURNING id"#,
+                item.product_id,
+                item.space_id,
+                item.stock_quantity,
+                item.best_by_date,
+            )
+            .fetch_one(&mut **tx)
+            .await
+            .inspect(|&id| {
+                change = Some(StockChange {
+                    stock_item_id: id,
+                    product_id: item.product_id,
+                    space_id: item.space_id,
+                    stock_quantity: item.stock_quantity,
+                });
+            })
+        }
+        path => {
+            return Ok(Err(OperationError::Unprocessable(format!(
+                "'{path}' isn't a creation endpoint that can be batched."
+            ))))
+        }
+    };
+
+    match result {
+        Ok(id) => Ok(Ok((id, change))),
+        Err(err) if is_unique_violation(&err) => Ok(Err(OperationError::Conflict(
+            "It conflicts with an existing row.".to_string(),
+        ))),
+        Err(sqlx::Error::Database(err)) if err.is_foreign_key_violation() => Ok(Err(
+            OperationError::Unprocessable("It references a row that doesn't exist.".to_string()),
+        )),
+        Err(err) => Err(InternalServerError(err)),
+    }
+}
+
+/// Relative difference under which two quantities count as equal, absorbing the rounding of
+/// the `real` quantity columns
+const QUANTITY_TOLERANCE: f64 = 1e-5;
+
+/// How long the background refresh of the stock totals waits for further changes to cover
+const STOCK_TOTALS_REFRESH_DELAY: Duration = Duration::from_secs(1);
+
+/// Refreshes the cached `product_stock_totals`, recording when in `stock_totals_refresh`
+async fn refresh_stock_totals(pool: &PgPool) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY product_stock_totals")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE stock_totals_refresh SET refreshed_at = LOCALTIMESTAMP")
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
+}
+
+/// Requests refreshes of the cached `product_stock_totals`, which a background task makes once
+/// `STOCK_TOTALS_REFRESH_DELAY` has passed, covering every request made in the meantime
+#[derive(Clone)]
+struct StockTotalsRefresher {
+    requested: Arc<Notify>,
+}
+
+impl StockTotalsRefresher {
+    /// Spawns the task refreshing the stock totals through `pool`
+    fn spawn(pool: PgPool) -> Self {
+        let requested = Arc::new(Notify::new());
+        let waiting = requested.clone();
+        tokio::spawn(async move {
+            loop {
+                waiting.notified().await;
+                tokio::time::sleep(STOCK_TOTALS_REFRESH_DELAY).await;
+                if let Err(err) = refresh_stock_totals(&pool).await {
+                    tracing::error!(%err, "failed to refresh stock totals");
+                }
+            }
+        });
+
+        Self { requested }
+    }
+
+    /// Requests a refresh, to be made after stock or the products have changed
+    fn request(&self) {
+        self.requested.notify_one();
+    }
+}
+
+/// Records the current total stock of every product, and also per space if `per_space` is set,
+/// returning the recorded rows
+async fn take_inventory_snapshot(
+    pool: &PgPool,
+    per_space: bool,
+) -> sqlx::Result<Vec<InventorySnapshot>> {
+    sqlx::query_as!(
+        InventorySnapshot,
+        r#"
+INSERT INTO inventory_snapshots (taken_at, product_id, space_id, total_quantity)
+SELECT LOCALTIMESTAMP, p.id, NULL::integer, COALESCE(SUM(si.stock_quantity), 0)::float8
+FROM products p
+LEFT JOIN stock_items si ON si.product_id = p.id
+GROUP BY p.id
+UNION ALL
+SELECT LOCALTIMESTAMP, si.product_id, si.space_id, SUM(si.stock_quantity)::float8
+FROM stock_items si
+WHERE $1
+GROUP BY si.product_id, si.space_id
+RETURNING id, taken_at, product_id, space_id, total_quantity"#,
+        per_space
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Zeroes every stock item past its best by date, recording an `Expire` entry for the quantity
+/// it held. Returns the changed stock items.
+async fn expire_stock(pool: &PgPool) -> sqlx::Result<Vec<StockChange>> {
+    sqlx::query_as!(
+        StockChange,
+        r#"
+WITH expired AS (
+    UPDATE stock_items si
+    SET stock_quantity = 0
+    FROM (
+        SELECT id, stock_quantity
+        FROM stock_items
+        WHERE best_by_date < CURRENT_DATE AND stock_quantity > 0
+        FOR UPDATE
+    ) e
+    WHERE si.id = e.id
+    RETURNING si.id, si.product_id, si.space_id, e.stock_quantity
+), recorded AS (
+    INSERT INTO stock_entries (entry_type, stock_quantity, stock_item_id, product_id, memo)
+    SELECT 'expire', stock_quantity, id, product_id, 'Expired automatically'
+    FROM expired
+)
+SELECT id AS "stock_item_id!", product_id AS "product_id!", space_id AS "space_id!",
+    0::real AS "stock_quantity!"
+FROM expired"#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Restores deleted unit conversion `id` within `tx`, checking it like a new conversion
+///
+/// Fails if the conversion isn't deleted, or if it would conflict with the conversions that
+/// replaced it. `tx` should be rolled back unless the restore succeeded.
+async fn restore_unit_conversion(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i32,
+) -> Result<RestoreResponse> {
+    let conversion = sqlx::query!(
+        "SELECT from_unit_id, to_unit_id, factor, deleted_at FROM unit_conversions WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+
+    let Some(conversion) = conversion else {
+        return Ok(RestoreResponse::NotFound(PlainText(format!(
+            "No unit conversion with id '{id}' found."
+        ))));
+    };
+    if conversion.deleted_at.is_none() {
+        return Ok(RestoreResponse::Conflict(PlainText(format!(
+            "Unit conversion '{id}' isn't deleted."
+        ))));
+    }
+    if let Some(existing_id) =
+        existing_unit_conversion(&mut **tx, conversion.from_unit_id, conversion.to_unit_id).await?
+    {
+        return Ok(RestoreResponse::Conflict(PlainText(format!(
+            "Unit conversion '{existing_id}' already converts unit '{}' to unit '{}'.",
+            conversion.from_unit_id, conversion.to_unit_id
+        ))));
+    }
+    if let Some(contradicting_id) = contradicting_unit_conversion(
+        &mut **tx,
+        conversion.from_unit_id,
+        conversion.to_unit_id,
+        conversion.factor,
+    )
+    .await?
+    {
+        return Ok(RestoreResponse::Conflict(PlainText(format!(
+            "Unit conversion '{contradicting_id}' converts unit '{}' to unit '{}' with a contradicting factor.",
+            conversion.to_unit_id, conversion.from_unit_id
+        ))));
+    }
+    if let Some(reason) = inconsistent_cycle(
+        &mut **tx,
+        conversion.from_unit_id,
+        conversion.to_unit_id,
+        conversion.factor,
+    )
+    .await?
+    {
+        return Ok(RestoreResponse::Conflict(PlainText(reason)));
+    }
+
+    let conversion = sqlx::query_as!(
+        UnitConversion,
+        r#"
+UPDATE unit_conversions
+SET deleted_at = NULL
+WHERE id = $1
+RETURNING *"#,
+        id
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(InternalServerError)?;
+
+    Ok(RestoreResponse::Success(Json(conversion)))
+}
+
+/// Checks that `quantity` is a whole number if the stock unit of product `product_id` is discrete,
+/// returning the reason it isn't otherwise
+async fn check_discrete_quantity(
+    executor: impl PgExecutor<'_>,
+    product_id: i32,
+    quantity: f32,
+) -> Result<Option<String>> {
+    let decimal_places = sqlx::query_scalar!(
+        r#"
+SELECT u.decimal_places
+FROM products p
+JOIN units u ON u.id = p.stock_unit_id
+WHERE p.id = $1"#,
+        product_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(InternalServerError)?
+    .flatten();
+
+    if decimal_places == Some(0) && quantity.fract() != 0.0 {
+        return Ok(Some(format!(
+            "Stock quantity '{quantity}' must be a whole number, as the product's stock unit is discrete."
+        )));
+    }
+
+    Ok(None)
+}
+
+/// Rounds `quantity` to the `decimal_places` of its unit, like `round_quantity` in the database,
+/// leaving it as is if the unit has none
+fn round_to_unit(quantity: f64, decimal_places: Option<i16>) -> f64 {
+    match decimal_places {
+        Some(decimal_places) => {
+            let scale = 10f64.powi(decimal_places.into());
+            (quantity * scale).round() / scale
+        }
+        None => quantity,
+    }
+}
+
+/// How far `total_quantity` is below `min_stock`, from 0 (at or above it, or no minimum stock)
+/// to 1 (out of stock)
+fn shortage_factor(min_stock: Option<f32>, total_quantity: f64) -> f64 {
+    min_stock
+        .filter(|&min_stock| min_stock > 0.0)
+        .map_or(0.0, |min_stock| {
+            let min_stock = f64::from(min_stock);
+            ((min_stock - total_quantity) / min_stock).clamp(0.0, 1.0)
+        })
+}
+
+/// Normalizes `tag` by trimming and lowercasing it
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Normalizes unit alias `alias` by trimming and lowercasing it
+fn normalize_alias(alias: &str) -> String {
+    alias.trim().to_lowercase()
+}
+
+/// The tags of product `product_id`, in alphabetical order
+async fn product_tags(executor: impl PgExecutor<'_>, product_id: i32) -> Result<Vec<String>> {
+    sqlx::query_scalar!(
+        r#"
+SELECT t.name
+FROM product_tags pt
+JOIN tags t ON t.id = pt.tag_id
+WHERE pt.product_id = $1
+ORDER BY t.name"#,
+        product_id
+    )
+    .fetch_all(executor)
+    .await
+    .map_err(InternalServerError)
+}
+
+/// Whether `err` is caused by a violated unique constraint
+fn is_unique_violation(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(err) if err.is_unique_violation())
+}
+
+/// Escapes `query` for use as a substring pattern with `LIKE`/`ILIKE`
+fn like_pattern(query: &str) -> String {
+    let escaped = query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+/// Builds the CORS middleware allowing the comma-separated `origins`, configured by the other
+/// `CORS_*` env variables
+fn cors(origins: &str) -> Cors {
+    let cors = Cors::new()
+        .allow_origins(
+            origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty()),
+        )
+        .allow_headers(["content-type", "x-api-key", "x-read-primary"])
+        .expose_headers(["x-cached-at", "x-cache-fresh"])
+        .allow_credentials(env_flag("CORS_ALLOW_CREDENTIALS"));
+
+    match env::var("CORS_MAX_AGE") {
+        Ok(max_age) => cors.max_age(
+            max_age
+                .parse()
+                .expect("env variable `CORS_MAX_AGE` should be a number of seconds"),
+        ),
+        Err(_) => cors,
+    }
+}
+
+/// Whether the flag `value` is set, meaning anything other than empty, `0` or `false`
+fn parse_flag(value: &str) -> bool {
+    !matches!(value.trim().to_lowercase().as_str(), "" | "0" | "false")
+}
+
+/// Whether the flag in env variable `name` is set
+fn env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| parse_flag(&value))
+}
+
+/// The number of seconds in env variable `name` that a report is cached for, 60 by default
+fn env_cache_ttl(name: &str) -> Duration {
+    Duration::from_secs(
+        env::var(name)
+            .map(|ttl| {
+                ttl.parse().unwrap_or_else(|_| {
+                    panic!("env variable `{name}` should be a number of seconds")
+                })
+            })
+            .unwrap_or(60),
+    )
+}
+
+#[derive(Tags)]
+enum ApiTags {
+    /// `POST` operations that only read, taking their input as a body, and are still served in
+    /// read-only mode
+    Reading,
+}
+
+struct UkisApi {
+    /// The most unit conversions a conversion may chain
+    max_conversion_path_length: usize,
+    /// The most characters a stock entry's memo may have
+    max_memo_length: usize,
+    /// Whether stock may be rebuilt from the stock entries
+    allow_stock_rebuild: bool,
+    /// Where stock changes are sent for `/events/stock` subscribers
+    stock_events: broadcast::Sender<StockChange>,
+    /// Refreshes the cached stock totals in the background
+    stock_totals: StockTotalsRefresher,
+    /// The recently computed turnover reports, per date window
+    turnover_cache: ReportCache<Vec<ProductTurnover>>,
+    /// The recently computed stock by value report
+    stock_by_value_cache: ReportCache<Vec<StockItemValue>>,
+}
+
+impl UkisApi {
+    /// Sends `changes` to every `/events/stock` subscriber
+    fn publish_stock_changes(&self, changes: Vec<StockChange>) {
+        for change in changes {
+            // Only fails when nobody is subscribed
+            let _ = self.stock_events.send(change);
+        }
+    }
+}
+
+#[OpenApi]
+impl UkisApi {
+    // PRODUCTS
+    /// Products: Fetch all
+    #[oai(path = "/products", method = "get")]
+    async fn get_products(
+        &self,
+        pool: Data<&PgPool>,
+        /// Only fetch products with this tag
+        tag: Query<Option<String>>,
+        /// Only fetch products with these comma-separated tags
+        #[oai(default, explode = false)]
+        tags: Query<Vec<String>>,
+        /// Whether products need all of the tags, or any of them (defaults to all)
+        tag_mode: Query<Option<TagMode>>,
+        /// Whether to include archived products
+        include_archived: Query<Option<bool>>,
+    ) -> Result<GetAllResponse<Product>> {
+        let mut tags: Vec<String> = tag
+            .0
+            .iter()
+            .chain(&tags.0)
+            .map(|tag| normalize_tag(tag))
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        tags.sort();
+        tags.dedup();
+
+        let products = sqlx::query_as!(
+            Product,
+            r#"
+SELECT id, name, description, parent_product_id, purchase_unit_id, stock_unit_id,
+    purchase_to_stock_factor, min_stock, max_stock, barcode, archived,
+    depletion_strategy AS "depletion_strategy: DepletionStrategy"
+FROM products
+WHERE ($3 OR NOT archived) AND (cardinality($1::text[]) = 0 OR id IN (
+    SELECT pt.product_id
+    FROM product_tags pt
+    JOIN tags t ON t.id = pt.tag_id
+    WHERE t.name = ANY($1)
+    GROUP BY pt.product_id
+    HAVING $2 OR COUNT(*) = cardinality($1)
+))"#,
+            &tags,
+            matches!(tag_mode.0, Some(TagMode::Any)),
+            include_archived.0.unwrap_or(false)
+        )
+        .fetch_all(pool.0)
+        .await
+        .unwrap();
+
+        Ok(Json(products))
+    }
+
+    /// Products: Fetch by id
+    #[oai(path = "/products/:id", method = "get")]
+    async fn get_product(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<Product>> {
+        let result: Option<Product> = sqlx::query_as!(
+            Product,
+            r#"
+SELECT id, name, description, parent_product_id, purchase_unit_id, stock_unit_id,
+    purchase_to_stock_factor, min_stock, max_stock, barcode, archived,
+    depletion_strategy AS "depletion_strategy: DepletionStrategy"
+FROM products
+WHERE id = $1"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(product) => Ok(GetResponse::Success(Json(product))),
+            None => Ok(GetResponse::NotFound(PlainText(
+                format!("No product with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    /// Products: Create new
+    #[oai(path = "/products", method = "post")]
+    async fn new_product(
+        &self,
+        pool: Data<&PgPool>,
+        product: Json<Product>,
+    ) -> Result<CreateResponse> {
+        if let (Some(min_stock), Some(max_stock)) = (product.min_stock, product.max_stock) {
+            if min_stock > max_stock {
+                return Ok(CreateResponse::Unprocessable(PlainText(format!(
+                    "Minimum stock '{min_stock}' is greater than maximum stock '{max_stock}'."
+                ))));
+            }
+        }
+
+        let record = sqlx::query!(
+            r#"
+INSERT INTO products (name, description, parent_product_id, purchase_unit_id, stock_unit_id, purchase_to_stock_factor, min_stock, max_stock, barcode, depletion_strategy)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+RETURNING id"#,
+            product.name,
+            product.description,
+            product.parent_product_id,
+            product.purchase_unit_id,
+            product.stock_unit_id,
+            product.purchase_to_stock_factor,
+            product.min_stock,
+            product.max_stock,
+            product.barcode,
+            product.depletion_strategy as DepletionStrategy
+        )
+        .fetch_one(pool.0)
+        .await;
+
+        match record {
+            Ok(record) => {
+                self.stock_totals.request();
+                Ok(CreateResponse::Success(Json(record.id)))
+            }
+            Err(err) if is_unique_violation(&err) => {
+                Ok(CreateResponse::Conflict(PlainText(format!(
+                    "A product with barcode '{}' already exists.",
+                    product.barcode.as_deref().unwrap_or_default()
+                ))))
+            }
+            Err(err) => Err(InternalServerError(err)),
+        }
+    }
+
+    /// Products: Create or update several by barcode
+    ///
+    /// Products with a barcode update the product with the same barcode if there is one, products
+    /// without one are always created. All products are upserted in a single transaction, none
+    /// are if any of them is invalid. The results are returned in the order the products were
+    /// given in.
+    #[oai(path = "/products/bulk", method = "put")]
+    async fn upsert_products(
+        &self,
+        pool: Data<&PgPool>,
+        products: Json<Vec<Product>>,
+    ) -> Result<BulkUpsertResponse> {
+        for (index, product) in products.iter().enumerate() {
+            if let (Some(min_stock), Some(max_stock)) = (product.min_stock, product.max_stock) {
+                if min_stock > max_stock {
+                    return Ok(BulkUpsertResponse::Unprocessable(PlainText(format!(
+                        "The product at index {index} has a minimum stock '{min_stock}' greater than its maximum stock '{max_stock}'."
+                    ))));
+                }
+            }
+        }
+
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let mut results = Vec::with_capacity(products.len());
+        for product in products.iter() {
+            let record = sqlx::query!(
+                r#"
+INSERT INTO products (name, description, parent_product_id, purchase_unit_id, stock_unit_id, purchase_to_stock_factor, min_stock, max_stock, barcode, depletion_strategy)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+ON CONFLICT (barcode) DO UPDATE
+SET name = EXCLUDED.name, description = EXCLUDED.description,
+    parent_product_id = EXCLUDED.parent_product_id, purchase_unit_id = EXCLUDED.purchase_unit_id,
+    stock_unit_id = EXCLUDED.stock_unit_id,
+    purchase_to_stock_factor = EXCLUDED.purchase_to_stock_factor,
+    min_stock = EXCLUDED.min_stock, max_stock = EXCLUDED.max_stock,
+    depletion_strategy = EXCLUDED.depletion_strategy
+RETURNING id, xmax = 0 AS "created!""#,
+                product.name,
+                product.description,
+                product.parent_product_id,
+                product.purchase_unit_id,
+                product.stock_unit_id,
+                product.purchase_to_stock_factor,
+                product.min_stock,
+                product.max_stock,
+                product.barcode,
+                product.depletion_strategy as DepletionStrategy
+            )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(InternalServerError)?;
+
+            results.push(UpsertResult {
+                id: record.id,
+                created: record.created,
+            });
+        }
+        tx.commit().await.map_err(InternalServerError)?;
+        self.stock_totals.request();
+
+        Ok(BulkUpsertResponse::Success(Json(results)))
+    }
+
+    /// Products: Update with id
+    ///
+    /// Replaces all fields of the product, except whether it's archived.
+    #[oai(path = "/products/:id", method = "put")]
+    async fn replace_product(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        product: Json<Product>,
+    ) -> Result<EditResponse<Product>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let response = update_product(&mut tx, id.0, &product).await?;
+        if let EditResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Products: Update some fields with id
+    #[oai(path = "/products/:id", method = "patch")]
+    async fn patch_product(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        patch: Json<ProductPatch>,
+    ) -> Result<EditResponse<Product>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let product = sqlx::query_as!(
+            Product,
+            r#"
+SELECT id, name, description, parent_product_id, purchase_unit_id, stock_unit_id,
+    purchase_to_stock_factor, min_stock, max_stock, barcode, archived,
+    depletion_strategy AS "depletion_strategy: DepletionStrategy"
+FROM products
+WHERE id = $1
+FOR UPDATE"#,
+            id.0
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let Some(mut product) = product else {
+            return Ok(EditResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        };
+        let patch = patch.0;
+        if let Some(name) = patch.name {
+            product.name = name;
+        }
+        patch.description.update_to(&mut product.description);
+        patch
+            .parent_product_id
+            .update_to(&mut product.parent_product_id);
+        patch
+            .purchase_unit_id
+            .update_to(&mut product.purchase_unit_id);
+        patch.stock_unit_id.update_to(&mut product.stock_unit_id);
+        patch
+            .purchase_to_stock_factor
+            .update_to(&mut product.purchase_to_stock_factor);
+        patch.min_stock.update_to(&mut product.min_stock);
+        patch.max_stock.update_to(&mut product.max_stock);
+        patch.barcode.update_to(&mut product.barcode);
+        if let Some(depletion_strategy) = patch.depletion_strategy {
+            product.depletion_strategy = depletion_strategy;
+        }
+
+        let response = update_product(&mut tx, id.0, &product).await?;
+        if let EditResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Products: Delete with id
+    #[oai(path = "/products/:id", method = "delete")]
+    async fn delete_product(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// Respond with `204 No Content` instead of echoing the deleted id
+        no_content: Query<Option<bool>>,
+    ) -> Result<DeleteResponse> {
+        let result = sqlx::query!(
+            r#"
+DELETE FROM products
+WHERE id = $1
+RETURNING id"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(_) => Ok(DeleteResponse::deleted(id.0, no_content.0)),
+            None => Ok(DeleteResponse::NotFound(PlainText(
+                format!("No product with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    /// Products: Archive with id
+    ///
+    /// Archived products are hidden from listings and search, but remain referenced by their
+    /// stock and entries.
+    #[oai(path = "/products/:id/archive", method = "post")]
+    async fn archive_product(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<UpdateResponse<Product>> {
+        set_product_archived(pool.0, id.0, true).await
+    }
+
+    /// Products: Restore an archived product
+    #[oai(path = "/products/:id/restore", method = "post")]
+    async fn restore_product(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<UpdateResponse<Product>> {
+        set_product_archived(pool.0, id.0, false).await
+    }
+
+    /// Products: Import by barcode from OpenFoodFacts
+    ///
+    /// Creates a product from the name and details OpenFoodFacts has for the barcode, storing the
+    /// barcode with it.
+    #[oai(path = "/products/import_barcode/:code", method = "post")]
+    async fn import_product_barcode(
+        &self,
+        pool: Data<&PgPool>,
+        open_food_facts: Data<&OpenFoodFacts>,
+        code: Path<String>,
+    ) -> Result<ImportResponse> {
+        if !(8..=14).contains(&code.0.len()) || !code.0.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(ImportResponse::BadRequest(PlainText(format!(
+                "Barcode '{}' isn't 8 to 14 digits.",
+                code.0
+            ))));
+        }
+        let exists = sqlx::query_scalar!("SELECT id FROM products WHERE barcode = $1", code.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?;
+        if let Some(id) = exists {
+            return Ok(ImportResponse::Conflict(PlainText(format!(
+                "Product '{id}' already has barcode '{}'.",
+                code.0
+            ))));
+        }
+
+        let product = match open_food_facts.product(&code.0).await {
+            Ok(product) => product,
+            Err(LookupError::NotFound) => {
+                return Ok(ImportResponse::NotFound(PlainText(format!(
+                    "No product with barcode '{}' found in OpenFoodFacts.",
+                    code.0
+                ))))
+            }
+            Err(LookupError::Timeout) => {
+                return Ok(ImportResponse::GatewayTimeout(PlainText(
+                    "OpenFoodFacts didn't respond in time.".to_string(),
+                )))
+            }
+            Err(LookupError::Upstream(reason)) => {
+                return Ok(ImportResponse::BadGateway(PlainText(format!(
+                    "OpenFoodFacts lookup failed: {reason}"
+                ))))
+            }
+        };
+
+        let record = sqlx::query!(
+            r#"
+INSERT INTO products (name, description, barcode)
+VALUES ($1, $2, $3)
+RETURNING id"#,
+            product.name,
+            product.description,
+            code.0
+        )
+        .fetch_one(pool.0)
+        .await;
+
+        match record {
+            Ok(record) => {
+                self.stock_totals.request();
+                Ok(ImportResponse::Success(Json(record.id)))
+            }
+            Err(err) if is_unique_violation(&err) => Ok(ImportResponse::Conflict(PlainText(
+                format!("A product with barcode '{}' already exists.", code.0),
+            ))),
+            Err(err) => Err(InternalServerError(err)),
+        }
+    }
+
+    /// Products: Fetch the tags
+    #[oai(path = "/products/:id/tags", method = "get")]
+    async fn get_product_tags(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<Vec<String>>> {
+        let exists = sqlx::query_scalar!("SELECT id FROM products WHERE id = $1", id.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?;
+        if exists.is_none() {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        Ok(GetResponse::Success(Json(
+            product_tags(pool.0, id.0).await?,
+        )))
+    }
+
+    /// Products: Add a tag
+    ///
+    /// Returns the product's tags.
+    #[oai(path = "/products/:id/tags", method = "post")]
+    async fn add_product_tag(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        tag: Json<NewTag>,
+    ) -> Result<TagResponse> {
+        let tag = normalize_tag(&tag.tag);
+        if tag.is_empty() {
+            return Ok(TagResponse::Unprocessable(PlainText(
+                "The tag must not be blank.".to_string(),
+            )));
+        }
+
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let exists = sqlx::query_scalar!("SELECT id FROM products WHERE id = $1", id.0)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(InternalServerError)?;
+        if exists.is_none() {
+            return Ok(TagResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        sqlx::query!(
+            r#"
+WITH tag AS (
+    INSERT INTO tags (name)
+    VALUES ($2)
+    ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+    RETURNING id
+)
+INSERT INTO product_tags (product_id, tag_id)
+SELECT $1, id FROM tag
+ON CONFLICT DO NOTHING"#,
+            id.0,
+            tag
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+        let tags = product_tags(&mut *tx, id.0).await?;
+        tx.commit().await.map_err(InternalServerError)?;
+
+        Ok(TagResponse::Success(Json(tags)))
+    }
+
+    /// Products: Remove a tag
+    ///
+    /// Returns the product's remaining tags.
+    #[oai(path = "/products/:id/tags/:tag", method = "delete")]
+    async fn remove_product_tag(
+        &self,
+        pool: Data<&PgPool>,
+        path: Path<(i32, String)>,
+    ) -> Result<UpdateResponse<Vec<String>>> {
+        let Path((id, tag)) = path;
+        let tag = normalize_tag(&tag);
+        let result = sqlx::query!(
+            r#"
+DELETE FROM product_tags pt
+USING tags t
+WHERE t.id = pt.tag_id AND pt.product_id = $1 AND t.name = $2"#,
+            id,
+            tag
+        )
+        .execute(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        if result.rows_affected() == 0 {
+            return Ok(UpdateResponse::NotFound(PlainText(format!(
+                "Product '{id}' isn't tagged '{tag}'."
+            ))));
+        }
+
+        Ok(UpdateResponse::Success(Json(
+            product_tags(pool.0, id).await?,
+        )))
+    }
+
+    /// Products: Fetch the average daily consumption
+    #[oai(path = "/products/:id/consumption_rate", method = "get")]
+    async fn get_product_consumption_rate(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// The number of days to average over (defaults to 30)
+        #[oai(validator(minimum(value = "1"), maximum(value = "3650")))]
+        days: Query<Option<i32>>,
+    ) -> Result<GetResponse<ConsumptionRate>> {
+        let days = days.0.unwrap_or(30);
+        let stock_unit_id =
+            sqlx::query_scalar!("SELECT stock_unit_id FROM products WHERE id = $1", id.0)
+                .fetch_optional(pool.0)
+                .await
+                .map_err(InternalServerError)?;
+
+        let Some(stock_unit_id) = stock_unit_id else {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        };
+
+        let entries = sqlx::query_as!(
+            StockEntry,
+            r#"
+SELECT e.id, e.entry_timestamp, e.entry_type AS "entry_type: EntryType", e.stock_quantity,
+    e.stock_item_id, e.product_id, e.place_id, e.to_space_id, e.price, e.memo,
+    e.status AS "status: EntryStatus"
+FROM stock_entries e
+LEFT JOIN stock_items si ON si.id = e.stock_item_id
+WHERE e.entry_type = 'consume' AND e.status = 'confirmed'
+  AND COALESCE(e.product_id, si.product_id) = $1
+  AND e.entry_timestamp >= LOCALTIMESTAMP - make_interval(days => $2)
+ORDER BY e.entry_timestamp"#,
+            id.0,
+            days
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let total_consumed: f64 = entries
+            .iter()
+            .map(|entry| f64::from(entry.stock_quantity))
+            .sum();
+
+        Ok(GetResponse::Success(Json(ConsumptionRate {
+            product_id: id.0,
+            days,
+            stock_unit_id,
+            total_consumed,
+            daily_rate: total_consumed / f64::from(days),
+            entries,
+        })))
+    }
+
+    /// Products: Fetch consumption per calendar month
+    ///
+    /// Sums the product's confirmed `Consume` entries within the last given number of years per
+    /// calendar month, listing all twelve months.
+    #[oai(path = "/products/:id/seasonality", method = "get")]
+    async fn get_product_seasonality(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// The number of years to look back over (defaults to 1)
+        #[oai(validator(minimum(value = "1"), maximum(value = "10")))]
+        years: Query<Option<i32>>,
+    ) -> Result<GetResponse<Seasonality>> {
+        let years = years.0.unwrap_or(1);
+        let exists = sqlx::query_scalar!("SELECT id FROM products WHERE id = $1", id.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?
+            .is_some();
+
+        if !exists {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let consumption = sqlx::query!(
+            r#"
+SELECT e.entry_timestamp, e.stock_quantity
+FROM stock_entries e
+LEFT JOIN stock_items si ON si.id = e.stock_item_id
+WHERE e.entry_type = 'consume' AND e.status = 'confirmed'
+  AND COALESCE(e.product_id, si.product_id) = $1
+  AND e.entry_timestamp >= LOCALTIMESTAMP - make_interval(years => $2)"#,
+            id.0,
+            years
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut months: Vec<MonthlyConsumption> = (1..=12)
+            .map(|month| MonthlyConsumption {
+                month,
+                consumed: 0.0,
+            })
+            .collect();
+        let mut data_years = HashSet::new();
+        for entry in consumption {
+            months[entry.entry_timestamp.month0() as usize].consumed +=
+                f64::from(entry.stock_quantity);
+            data_years.insert(entry.entry_timestamp.year());
+        }
+
+        Ok(GetResponse::Success(Json(Seasonality {
+            product_id: id.0,
+            years,
+            data_years: data_years.len() as i64,
+            months,
+        })))
+    }
+
+    /// Products: Fetch how often it is purchased
+    ///
+    /// Counts the product's confirmed `Purchase` entries within the last given number of days,
+    /// along with the average time between them.
+    #[oai(path = "/products/:id/purchase_frequency", method = "get")]
+    async fn get_product_purchase_frequency(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// The number of days to count over (defaults to 90)
+        #[oai(validator(minimum(value = "1"), maximum(value = "3650")))]
+        days: Query<Option<i32>>,
+    ) -> Result<GetResponse<PurchaseFrequency>> {
+        let days = days.0.unwrap_or(90);
+        let exists = sqlx::query_scalar!("SELECT id FROM products WHERE id = $1", id.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?
+            .is_some();
+
+        if !exists {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let frequency = sqlx::query!(
+            r#"
+SELECT COUNT(*) AS "purchase_count!",
+    (EXTRACT(EPOCH FROM MAX(e.entry_timestamp) - MIN(e.entry_timestamp)) / 86400
+        / NULLIF(COUNT(*) - 1, 0))::float8 AS average_interval_days
+FROM stock_entries e
+LEFT JOIN stock_items si ON si.id = e.stock_item_id
+WHERE e.entry_type = 'purchase' AND e.status = 'confirmed'
+  AND COALESCE(e.product_id, si.product_id) = $1
+  AND e.entry_timestamp >= LOCALTIMESTAMP - make_interval(days => $2)"#,
+            id.0,
+            days
+        )
+        .fetch_one(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(GetResponse::Success(Json(PurchaseFrequency {
+            product_id: id.0,
+            days,
+            purchase_count: frequency.purchase_count,
+            average_interval_days: frequency.average_interval_days,
+        })))
+    }
+
+    /// Products: Fetch the average time from purchase to consumption
+    ///
+    /// Matches the product's confirmed `Consume` entries to its `Purchase` entries first in,
+    /// first out, averaging the time in between. Expired stock is matched too, but left out of
+    /// the average.
+    #[oai(path = "/products/:id/dwell_time", method = "get")]
+    async fn get_product_dwell_time(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<DwellTime>> {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM products WHERE id = $1) AS "exists!""#,
+            id.0
+        )
+        .fetch_one(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+        if !exists {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let entries = sqlx::query!(
+            r#"
+SELECT e.entry_timestamp, e.entry_type AS "entry_type: EntryType", e.stock_quantity
+FROM stock_entries e
+LEFT JOIN stock_items si ON si.id = e.stock_item_id
+WHERE e.entry_type IN ('purchase', 'consume', 'expire') AND e.status = 'confirmed'
+  AND COALESCE(e.product_id, si.product_id) = $1
+ORDER BY e.entry_timestamp, e.id"#,
+            id.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut purchases = VecDeque::new();
+        let mut total_days = 0.0;
+        let mut sample_count = 0;
+        let mut matched_quantity = 0.0;
+        let mut unmatched_quantity = 0.0;
+        for entry in entries {
+            let quantity = f64::from(entry.stock_quantity);
+            if let EntryType::Purchase = entry.entry_type {
+                purchases.push_back((entry.entry_timestamp, quantity));
+                continue;
+            }
+            let consumed = matches!(entry.entry_type, EntryType::Consume);
+
+            let mut remaining = quantity;
+            while remaining > 0.0 {
+                let Some((purchased_at, available)) = purchases.front_mut() else {
+                    break;
+                };
+                let matched = remaining.min(*available);
+                if consumed {
+                    let days =
+                        (entry.entry_timestamp - *purchased_at).num_seconds() as f64 / 86400.0;
+                    total_days += days * matched;
+                    matched_quantity += matched;
+                }
+                remaining -= matched;
+                *available -= matched;
+                if *available <= 0.0 {
+                    purchases.pop_front();
+                }
+            }
+            if consumed {
+                if remaining < quantity {
+                    sample_count += 1;
+                }
+                unmatched_quantity += remaining;
+            }
+        }
+
+        Ok(GetResponse::Success(Json(DwellTime {
+            product_id: id.0,
+            average_days: (matched_quantity > 0.0).then(|| total_days / matched_quantity),
+            sample_count,
+            matched_quantity,
+            unmatched_quantity,
+        })))
+    }
+
+    /// Products: Change the depletion strategy
+    #[oai(path = "/products/:id/depletion_strategy", method = "put")]
+    async fn set_product_depletion_strategy(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        depletion_strategy: Json<DepletionStrategy>,
+    ) -> Result<UpdateResponse<Product>> {
+        let product = sqlx::query_as!(
+            Product,
+            r#"
+UPDATE products
+SET depletion_strategy = $2
+WHERE id = $1
+RETURNING id, name, description, parent_product_id, purchase_unit_id, stock_unit_id,
+    purchase_to_stock_factor, min_stock, max_stock, barcode, archived,
+    depletion_strategy AS "depletion_strategy: DepletionStrategy""#,
+            id.0,
+            depletion_strategy.0 as DepletionStrategy
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match product {
+            Some(product) => Ok(UpdateResponse::Success(Json(product))),
+            None => Ok(UpdateResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            )))),
+        }
+    }
+
+    /// Products: Fetch stock per lot
+    ///
+    /// Groups the product's stock items by best by date, in the order they are used up: soonest
+    /// best by date first (and undated stock last) for `fefo` products, or first received first
+    /// for `fifo` products.
+    #[oai(path = "/products/:id/lots", method = "get")]
+    async fn get_product_lots(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<Vec<ProductLot>>> {
+        let exists = sqlx::query_scalar!("SELECT id FROM products WHERE id = $1", id.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?
+            .is_some();
+
+        if !exists {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let items = sqlx::query!(
+            r#"
+SELECT si.id, si.best_by_date, si.stock_quantity, s.id AS space_id, s.name AS space_name
+FROM stock_items si
+JOIN spaces s ON s.id = si.space_id
+JOIN products p ON p.id = si.product_id
+WHERE si.product_id = $1
+ORDER BY CASE WHEN p.depletion_strategy = 'fefo' THEN si.best_by_date END NULLS LAST,
+    MIN(si.created_at) OVER (PARTITION BY si.best_by_date), si.best_by_date, s.name"#,
+            id.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut lots: Vec<ProductLot> = Vec::new();
+        for item in items {
+            let stock = LotStock {
+                stock_item_id: item.id,
+                space_id: item.space_id,
+                space_name: item.space_name,
+                stock_quantity: item.stock_quantity,
+            };
+
+            match lots.last_mut() {
+                Some(lot) if lot.best_by_date == item.best_by_date => {
+                    lot.total_quantity += f64::from(stock.stock_quantity);
+                    lot.stock.push(stock);
+                }
+                _ => lots.push(ProductLot {
+                    best_by_date: item.best_by_date,
+                    total_quantity: f64::from(stock.stock_quantity),
+                    stock: vec![stock],
+                }),
+            }
+        }
+
+        Ok(GetResponse::Success(Json(lots)))
+    }
+
+    /// Products: Fetch stock per space
+    ///
+    /// Lists where the product is stored, ordered by space name and then best by date.
+    #[oai(path = "/products/:id/distribution", method = "get")]
+    async fn get_product_distribution(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<ProductDistribution>> {
+        let exists = sqlx::query_scalar!("SELECT id FROM products WHERE id = $1", id.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?
+            .is_some();
+
+        if !exists {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let stock = sqlx::query_as!(
+            DistributedStock,
+            r#"
+SELECT si.id AS stock_item_id, s.id AS space_id, s.name AS space_name, si.stock_quantity,
+    si.best_by_date
+FROM stock_items si
+JOIN spaces s ON s.id = si.space_id
+WHERE si.product_id = $1
+ORDER BY s.name, si.best_by_date NULLS LAST"#,
+            id.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+        let total_quantity = stock
+            .iter()
+            .map(|item| f64::from(item.stock_quantity))
+            .sum();
+
+        Ok(GetResponse::Success(Json(ProductDistribution {
+            product_id: id.0,
+            stock,
+            total_quantity,
+        })))
+    }
+
+    /// Products: Fetch the share of stock per space
+    ///
+    /// Lists the spaces holding the product, largest quantity first, with their share of its
+    /// total stock. Empty if the product isn't in stock.
+    #[oai(path = "/products/:id/space_breakdown", method = "get")]
+    async fn get_product_space_breakdown(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<Vec<SpaceShare>>> {
+        let exists = sqlx::query_scalar!("SELECT id FROM products WHERE id = $1", id.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?
+            .is_some();
+
+        if !exists {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let spaces = sqlx::query!(
+            r#"
+SELECT s.id, s.name, SUM(si.stock_quantity)::float8 AS "stock_quantity!"
+FROM stock_items si
+JOIN spaces s ON s.id = si.space_id
+WHERE si.product_id = $1
+GROUP BY s.id
+HAVING SUM(si.stock_quantity) > 0
+ORDER BY 3 DESC, s.name"#,
+            id.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+        let total_quantity: f64 = spaces.iter().map(|space| space.stock_quantity).sum();
+
+        let shares = spaces
+            .into_iter()
+            .map(|space| SpaceShare {
+                space_id: space.id,
+                space_name: space.name,
+                stock_quantity: space.stock_quantity,
+                percentage: space.stock_quantity / total_quantity * 100.0,
+            })
+            .collect();
+
+        Ok(GetResponse::Success(Json(shares)))
+    }
+
+    /// Products: Change the best by date of stock
+    ///
+    /// Moves all of the product's stock items from one best by date to another, returning the
+    /// number of stock items changed. Stock items that would then share a space and best by date
+    /// with another are merged into it.
+    #[oai(path = "/products/:id/stock/best_by_date", method = "patch")]
+    async fn change_product_best_by_date(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        change: Json<BestByDateChange>,
+    ) -> Result<UpdateResponse<u64>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let exists = sqlx::query_scalar!("SELECT id FROM products WHERE id = $1", id.0)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(InternalServerError)?
+            .is_some();
+
+        if !exists {
+            return Ok(UpdateResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        }
+        if change.from_date == change.to_date {
+            return Ok(UpdateResponse::Success(Json(0)));
+        }
+
+        let merged = sqlx::query!(
+            r#"
+UPDATE stock_items target
+SET stock_quantity = target.stock_quantity + source.stock_quantity
+FROM stock_items source
+WHERE source.product_id = $1 AND source.best_by_date IS NOT DISTINCT FROM $2
+  AND target.product_id = $1 AND target.best_by_date IS NOT DISTINCT FROM $3
+  AND target.space_id = source.space_id
+RETURNING source.id AS source_id, target.id AS target_id, target.space_id,
+    target.stock_quantity"#,
+            id.0,
+            change.from_date,
+            change.to_date
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        for merge in &merged {
+            sqlx::query!(
+                "UPDATE stock_entries SET stock_item_id = $2 WHERE stock_item_id = $1",
+                merge.source_id,
+                merge.target_id
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(InternalServerError)?;
+            sqlx::query!("DELETE FROM stock_items WHERE id = $1", merge.source_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(InternalServerError)?;
+        }
+
+        let updated = sqlx::query!(
+            r#"
+UPDATE stock_items
+SET best_by_date = $3
+WHERE product_id = $1 AND best_by_date IS NOT DISTINCT FROM $2"#,
+            id.0,
+            change.from_date,
+            change.to_date
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(InternalServerError)?
+        .rows_affected();
+        tx.commit().await.map_err(InternalServerError)?;
+        self.stock_totals.request();
+        self.publish_stock_changes(
+            merged
+                .iter()
+                .flat_map(|merge| {
+                    [
+                        StockChange {
+                            stock_item_id: merge.source_id,
+                            product_id: id.0,
+                            space_id: merge.space_id,
+                            stock_quantity: 0.0,
+                        },
+                        StockChange {
+                            stock_item_id: merge.target_id,
+                            product_id: id.0,
+                            space_id: merge.space_id,
+                            stock_quantity: merge.stock_quantity,
+                        },
+                    ]
+                })
+                .collect(),
+        );
+
+        Ok(UpdateResponse::Success(Json(merged.len() as u64 + updated)))
+    }
+
+    /// Products: Change the reorder thresholds of several products
+    ///
+    /// Sets each product's minimum stock to its reorder threshold, in a single transaction. If
+    /// any of them can't be updated, none of them are.
+    #[oai(path = "/products/reorder_thresholds", method = "patch")]
+    async fn update_reorder_thresholds(
+        &self,
+        pool: Data<&PgPool>,
+        updates: Json<Vec<ThresholdUpdate>>,
+    ) -> Result<ThresholdUpdateResponse> {
+        if let Some(update) = updates.iter().find(|update| update.reorder_threshold < 0.0) {
+            return Ok(ThresholdUpdateResponse::BadRequest(PlainText(format!(
+                "Reorder threshold '{}' of product '{}' is negative.",
+                update.reorder_threshold, update.product_id
+            ))));
+        }
+
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let mut results = Vec::with_capacity(updates.len());
+        for update in updates.0 {
+            let Some(product) = sqlx::query!(
+                "SELECT min_stock, max_stock FROM products WHERE id = $1 FOR UPDATE",
+                update.product_id
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(InternalServerError)?
+            else {
+                return Ok(ThresholdUpdateResponse::NotFound(PlainText(format!(
+                    "No product with id '{}' found.",
+                    update.product_id
+                ))));
+            };
+            if let Some(max_stock) = product.max_stock {
+                if update.reorder_threshold > max_stock {
+                    return Ok(ThresholdUpdateResponse::Unprocessable(PlainText(format!(
+                        "Reorder threshold '{}' of product '{}' is greater than its maximum stock '{max_stock}'.",
+                        update.reorder_threshold, update.product_id
+                    ))));
+                }
+            }
+
+            sqlx::query!(
+                "UPDATE products SET min_stock = $2 WHERE id = $1",
+                update.product_id,
+                update.reorder_threshold
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(InternalServerError)?;
+            results.push(ThresholdUpdateResult {
+                product_id: update.product_id,
+                previous_threshold: product.min_stock,
+                reorder_threshold: update.reorder_threshold,
+            });
+        }
+        tx.commit().await.map_err(InternalServerError)?;
+
+        Ok(ThresholdUpdateResponse::Success(Json(results)))
+    }
+
+    /// Products: Fetch the effective factor of purchase unit to stock unit
+    ///
+    /// Prefers the product's own `purchase_to_stock_factor`, falling back to converting between
+    /// its purchase and stock units.
+    #[oai(path = "/products/:id/purchase_factor", method = "get")]
+    async fn get_product_purchase_factor(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// The number of decimal places to round the factor to (unrounded by default)
+        #[oai(validator(maximum(value = "10")))]
+        precision: Query<Option<u32>>,
+    ) -> Result<ConversionResponse<PurchaseFactor>> {
+        let product = sqlx::query!(
+            r#"
+SELECT purchase_unit_id, stock_unit_id, purchase_to_stock_factor
+FROM products
+WHERE id = $1"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let Some(product) = product else {
+            return Ok(ConversionResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        };
+
+        let (factor, source) = match (
+            product.purchase_to_stock_factor,
+            product.purchase_unit_id,
+            product.stock_unit_id,
+        ) {
+            (Some(factor), _, _) => (Ok(f64::from(factor)), PurchaseFactorSource::Product),
+            (None, Some(purchase_unit_id), Some(stock_unit_id)) => {
+                let graph = ConversionGraph::load(pool.0, self.max_conversion_path_length)
+                    .await
+                    .map_err(InternalServerError)?;
+
+                (
+                    graph.factor(purchase_unit_id, stock_unit_id),
+                    PurchaseFactorSource::Conversions,
+                )
+            }
+            _ => (
+                Err(ConversionError::NoPath),
+                PurchaseFactorSource::Conversions,
+            ),
+        };
+
+        let factor = factor.map(|factor| {
+            precision.0.map_or(factor, |decimal_places| {
+                round_half_even(factor, decimal_places)
+            })
+        });
+        match factor {
+            Ok(factor) => Ok(ConversionResponse::Success(Json(PurchaseFactor {
+                product_id: id.0,
+                purchase_unit_id: product.purchase_unit_id,
+                stock_unit_id: product.stock_unit_id,
+                factor,
+                source,
+            }))),
+            Err(ConversionError::NoPath) => Ok(ConversionResponse::Unprocessable(PlainText(format!(
+                "Product '{}' has no purchase to stock factor, and none can be derived from the unit conversions.",
+                id.0
+            )))),
+            Err(ConversionError::PathTooLong { length }) => {
+                Ok(ConversionResponse::Unprocessable(PlainText(format!(
+                    "Conversion path too long: converting the purchase unit of product '{}' to its stock unit takes {length} conversions, more than the maximum of {}.",
+                    id.0, self.max_conversion_path_length
+                ))))
+            }
+        }
+    }
+
+    /// Products: Convert a quantity to the stock unit
+    ///
+    /// Converts a quantity in any unit to the product's stock unit, using the product's own
+    /// `purchase_to_stock_factor` for quantities in its purchase unit and the unit conversions
+    /// otherwise.
+    #[oai(
+        path = "/products/:id/normalize_quantity",
+        method = "post",
+        tag = "ApiTags::Reading"
+    )]
+    async fn normalize_product_quantity(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        quantity: Json<UnitQuantity>,
+        /// The number of decimal places to round the quantity and factor to (unrounded by default)
+        #[oai(validator(maximum(value = "10")))]
+        precision: Query<Option<u32>>,
+    ) -> Result<ConversionResponse<NormalizedQuantity>> {
+        let product = sqlx::query!(
+            r#"
+SELECT purchase_unit_id, stock_unit_id, purchase_to_stock_factor
+FROM products
+WHERE id = $1"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let Some(product) = product else {
+            return Ok(ConversionResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                id.0
+            ))));
+        };
+        let Some(stock_unit_id) = product.stock_unit_id else {
+            return Ok(ConversionResponse::Unprocessable(PlainText(format!(
+                "Product '{}' has no stock unit.",
+                id.0
+            ))));
+        };
+
+        let factor = match product.purchase_to_stock_factor {
+            Some(factor) if product.purchase_unit_id == Some(quantity.unit_id) => {
+                Ok(f64::from(factor))
+            }
+            _ => ConversionGraph::load(pool.0, self.max_conversion_path_length)
+                .await
+                .map_err(InternalServerError)?
+                .factor(quantity.unit_id, stock_unit_id),
+        };
+
+        let round = |value| {
+            precision.0.map_or(value, |decimal_places| {
+                round_half_even(value, decimal_places)
+            })
+        };
+        match factor {
+            Ok(factor) => Ok(ConversionResponse::Success(Json(NormalizedQuantity {
+                product_id: id.0,
+                stock_unit_id,
+                quantity: round(quantity.quantity * factor),
+                factor: round(factor),
+            }))),
+            Err(ConversionError::NoPath) => Ok(ConversionResponse::Unprocessable(PlainText(format!(
+                "Unit '{}' can't be converted to the stock unit '{stock_unit_id}' of product '{}'.",
+                quantity.unit_id, id.0
+            )))),
+            Err(ConversionError::PathTooLong { length }) => {
+                Ok(ConversionResponse::Unprocessable(PlainText(format!(
+                    "Conversion path too long: converting unit '{}' to the stock unit of product '{}' takes {length} conversions, more than the maximum of {}.",
+                    quantity.unit_id, id.0, self.max_conversion_path_length
+                ))))
+            }
+        }
+    }
+
+    // UNITS
+    /// Units: Fetch all
+    #[oai(path = "/units", method = "get")]
+    async fn get_units(
+        &self,
+        pool: Data<&PgPool>,
+        /// Whether to include archived units
+        include_archived: Query<Option<bool>>,
+    ) -> Result<GetAllResponse<Unit>> {
+        let units = sqlx::query_as!(
+            Unit,
+            "SELECT * FROM units WHERE $1 OR NOT archived",
+            include_archived.0.unwrap_or(false)
+        )
+        .fetch_all(pool.0)
+        .await
+        .unwrap();
+
+        Ok(Json(units))
+    }
+
+    /// Units: Fetch by id
+    #[oai(path = "/units/:id", method = "get")]
+    async fn get_unit(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<GetResponse<Unit>> {
+        let unit: Option<Unit> = sqlx::query_as!(Unit, "SELECT * FROM units WHERE id = $1", id.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?;
+
+        match unit {
+            Some(unit) => Ok(GetResponse::Success(Json(unit))),
+            None => Ok(GetResponse::NotFound(PlainText(
+                format!("No unit with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    /// Units: Fetch all units a unit converts to
+    ///
+    /// Includes units only reachable through a chain of conversions, of at most
+    /// `MAX_CONVERSION_PATH_LENGTH` conversions.
+    #[oai(path = "/units/:id/conversions", method = "get")]
+    async fn get_unit_conversions_from(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// The number of decimal places to round the factors to (unrounded by default)
+        #[oai(validator(maximum(value = "10")))]
+        precision: Query<Option<u32>>,
+    ) -> Result<GetResponse<Vec<UnitEquivalent>>> {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM units WHERE id = $1) AS "exists!""#,
+            id.0
+        )
+        .fetch_one(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+        if !exists {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No unit with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let graph = ConversionGraph::load(pool.0, self.max_conversion_path_length)
+            .await
+            .map_err(InternalServerError)?;
+        let equivalents = graph
+            .reachable(id.0)
+            .into_iter()
+            .map(|(to_unit_id, cumulative_factor)| UnitEquivalent {
+                to_unit_id,
+                cumulative_factor: precision.0.map_or(cumulative_factor, |decimal_places| {
+                    round_half_even(cumulative_factor, decimal_places)
+                }),
+            })
+            .collect();
+
+        Ok(GetResponse::Success(Json(equivalents)))
+    }
+
+    /// Units: Resolve by name
+    ///
+    /// Finds the unit whose singular form, plural form or alias is the given text, ignoring case
+    /// and surrounding whitespace.
+    #[oai(path = "/units/resolve/:text", method = "get")]
+    async fn resolve_unit(
+        &self,
+        pool: Data<&PgPool>,
+        text: Path<String>,
+    ) -> Result<GetResponse<Unit>> {
+        let unit = sqlx::query_as!(
+            Unit,
+            r#"
+SELECT u.*
+FROM units u
+LEFT JOIN unit_aliases a ON a.unit_id = u.id AND a.alias = $1
+WHERE lower(u.singular) = $1 OR lower(u.plural) = $1 OR a.alias IS NOT NULL
+ORDER BY lower(u.singular) = $1 DESC, lower(u.plural) = $1 DESC, u.id
+LIMIT 1"#,
+            normalize_alias(&text.0)
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match unit {
+            Some(unit) => Ok(GetResponse::Success(Json(unit))),
+            None => Ok(GetResponse::NotFound(PlainText(format!(
+                "No unit named '{}' found.",
+                text.0
+            )))),
+        }
+    }
+
+    /// Units: Add an alias
+    ///
+    /// Returns all of the unit's aliases. An alias can only belong to one unit, and can't be
+    /// another unit's singular or plural form.
+    #[oai(path = "/units/:id/aliases", method = "post")]
+    async fn add_unit_alias(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        alias: Json<NewUnitAlias>,
+    ) -> Result<AliasResponse> {
+        let alias = normalize_alias(&alias.alias);
+        if alias.is_empty() {
+            return Ok(AliasResponse::Unprocessable(PlainText(
+                "The alias must not be blank.".to_string(),
+            )));
+        }
+
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let exists = sqlx::query_scalar!("SELECT id FROM units WHERE id = $1", id.0)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(InternalServerError)?;
+        if exists.is_none() {
+            return Ok(AliasResponse::NotFound(PlainText(format!(
+                "No unit with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let owner = sqlx::query_scalar!(
+            r#"
+SELECT id AS "id!" FROM units WHERE lower(singular) = $1 OR lower(plural) = $1
+UNION ALL
+SELECT unit_id FROM unit_aliases WHERE alias = $1
+LIMIT 1"#,
+            alias
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+        match owner {
+            Some(unit_id) if unit_id != id.0 => {
+                return Ok(AliasResponse::Conflict(PlainText(format!(
+                    "'{alias}' already names unit '{unit_id}'."
+                ))))
+            }
+            Some(_) => {}
+            None => {
+                let result = sqlx::query!(
+                    "INSERT INTO unit_aliases (alias, unit_id) VALUES ($1, $2)",
+                    alias,
+                    id.0
+                )
+                .execute(&mut *tx)
+                .await;
+                match result {
+                    Ok(_) => {}
+                    Err(err) if is_unique_violation(&err) => {
+                        return Ok(AliasResponse::Conflict(PlainText(format!(
+                            "The alias '{alias}' already exists."
+                        ))))
+                    }
+                    Err(err) => return Err(InternalServerError(err)),
+                }
+            }
+        }
+
+        let aliases = sqlx::query_scalar!(
+            "SELECT alias FROM unit_aliases WHERE unit_id = $1 ORDER BY alias",
+            id.0
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+        tx.commit().await.map_err(InternalServerError)?;
+
+        Ok(AliasResponse::Success(Json(aliases)))
+    }
+
+    /// Units: Create new
+    #[oai(path = "/units", method = "post")]
+    async fn new_unit(&self, pool: Data<&PgPool>, unit: Json<Unit>) -> Result<CreateResponse> {
+        let result = sqlx::query!(
+            r#"
+INSERT INTO units (singular, plural, decimal_places)
+VALUES ($1, $2, $3)
+RETURNING id"#,
+            unit.singular,
+            unit.plural,
+            unit.decimal_places,
+        )
+        .fetch_one(pool.0)
+        .await;
+
+        match result {
+            Ok(record) => Ok(CreateResponse::Success(Json(record.id))),
+            Err(err) if is_unique_violation(&err) => Ok(CreateResponse::Conflict(PlainText(
+                format!("A unit '{}' already exists.", unit.singular),
+            ))),
+            Err(err) => Err(InternalServerError(err)),
+        }
+    }
+
+    /// Units: Create several at once
+    ///
+    /// All units are created in a single transaction, none are created if any of them already
+    /// exists. The ids are returned in the order the units were given in.
+    #[oai(path = "/units/bulk", method = "post")]
+    async fn new_units(
+        &self,
+        pool: Data<&PgPool>,
+        units: Json<Vec<Unit>>,
+    ) -> Result<BulkCreateResponse> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let mut ids = Vec::with_capacity(units.len());
+
+        for (index, unit) in units.iter().enumerate() {
+            let result = sqlx::query!(
+                r#"
+INSERT INTO units (singular, plural, decimal_places)
+VALUES ($1, $2, $3)
+RETURNING id"#,
+                unit.singular,
+                unit.plural,
+                unit.decimal_places,
+            )
+            .fetch_one(&mut *tx)
+            .await;
+
+            match result {
+                Ok(record) => ids.push(record.id),
+                Err(err) if is_unique_violation(&err) => {
+                    return Ok(BulkCreateResponse::Conflict(PlainText(format!(
+                        "The unit at index {index} ('{}') already exists.",
+                        unit.singular
+                    ))))
+                }
+                Err(err) => return Err(InternalServerError(err)),
+            }
+        }
+        tx.commit().await.map_err(InternalServerError)?;
+
+        Ok(BulkCreateResponse::Success(Json(ids)))
+    }
+
+    /// Units: Update with id
+    ///
+    /// Replaces all fields of the unit, except whether it's archived.
+    #[oai(path = "/units/:id", method = "put")]
+    async fn replace_unit(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        unit: Json<Unit>,
+    ) -> Result<EditResponse<Unit>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let response = update_unit(&mut tx, id.0, &unit).await?;
+        if let EditResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Units: Update some fields with id
+    #[oai(path = "/units/:id", method = "patch")]
+    async fn patch_unit(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        patch: Json<UnitPatch>,
+    ) -> Result<EditResponse<Unit>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let unit = sqlx::query_as!(
+            Unit,
+            r#"
+SELECT * FROM units WHERE id = $1 FOR UPDATE"#,
+            id.0
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let Some(mut unit) = unit else {
+            return Ok(EditResponse::NotFound(PlainText(format!(
+                "No unit with id '{}' found.",
+                id.0
+            ))));
+        };
+        let patch = patch.0;
+        if let Some(singular) = patch.singular {
+            unit.singular = singular;
+        }
+        patch.plural.update_to(&mut unit.plural);
+        patch.decimal_places.update_to(&mut unit.decimal_places);
+
+        let response = update_unit(&mut tx, id.0, &unit).await?;
+        if let EditResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Units: Delete with id
+    ///
+    /// Units still referenced by products or unit conversions are archived instead.
+    #[oai(path = "/units/:id", method = "delete")]
+    async fn delete_unit(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// Respond with `204 No Content` instead of echoing the deleted id
+        no_content: Query<Option<bool>>,
+    ) -> Result<DeleteResponse> {
+        let result = sqlx::query!(
+            r#"
+WITH referenced AS (
+    SELECT EXISTS (
+        SELECT 1 FROM products WHERE purchase_unit_id = $1 OR stock_unit_id = $1
+    ) OR EXISTS (
+        SELECT 1 FROM unit_conversions WHERE from_unit_id = $1 OR to_unit_id = $1
+    ) AS referenced
+), archived AS (
+    UPDATE units
+    SET archived = true
+    WHERE id = $1 AND (SELECT referenced FROM referenced)
+    RETURNING id
+), deleted AS (
+    DELETE FROM units
+    WHERE id = $1 AND NOT (SELECT referenced FROM referenced)
+    RETURNING id
+)
+SELECT id FROM archived
+UNION ALL
+SELECT id FROM deleted"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(_) => Ok(DeleteResponse::deleted(id.0, no_content.0)),
+            None => Ok(DeleteResponse::NotFound(PlainText(
+                format!("No unit with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    // UNIT CONVERSIONS
+    /// Unit Conversions: Fetch all
+    #[oai(path = "/unit_conversions", method = "get")]
+    async fn get_unit_conversions(
+        &self,
+        pool: Data<&PgPool>,
+        /// Whether to include deleted unit conversions
+        include_deleted: Query<Option<bool>>,
+    ) -> Result<GetAllResponse<UnitConversion>> {
+        let unit_conversions = sqlx::query_as!(
+            UnitConversion,
+            "SELECT * FROM unit_conversions WHERE $1 OR deleted_at IS NULL",
+            include_deleted.0.unwrap_or(false)
+        )
+        .fetch_all(pool.0)
+        .await
+        .unwrap();
+
+        Ok(Json(unit_conversions))
+    }
+
+    /// Unit Conversions: Fetch conflicting conversions
+    ///
+    /// Finds the pairs of conversions between the same two units, in either direction, whose
+    /// factors contradict each other.
+    #[oai(path = "/unit_conversions/conflicts", method = "get")]
+    async fn get_unit_conversion_conflicts(
+        &self,
+        pool: Data<&PgPool>,
+        /// The relative difference up to which factors are considered equal (defaults to 0.001)
+        #[oai(validator(minimum(value = "0")))]
+        tolerance: Query<Option<f64>>,
+    ) -> Result<GetAllResponse<ConversionConflict>> {
+        let tolerance = tolerance.0.unwrap_or(DEFAULT_FACTOR_TOLERANCE);
+        let pairs = sqlx::query!(
+            r#"
+SELECT a.id AS first_id, b.id AS second_id, a.from_unit_id, a.to_unit_id,
+    a.factor::float8 AS "first_factor!",
+    (CASE WHEN a.from_unit_id = b.from_unit_id THEN b.factor ELSE 1 / b.factor END)::float8
+        AS "second_factor!"
+FROM unit_conversions a
+JOIN unit_conversions b ON a.id < b.id
+    AND ((a.from_unit_id = b.from_unit_id AND a.to_unit_id = b.to_unit_id)
+        OR (a.from_unit_id = b.to_unit_id AND a.to_unit_id = b.from_unit_id))
+WHERE a.deleted_at IS NULL AND b.deleted_at IS NULL
+    AND a.factor IS NOT NULL AND a.factor <> 0 AND b.factor IS NOT NULL AND b.factor <> 0
+ORDER BY a.id, b.id"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let conflicts = pairs
+            .into_iter()
+            .filter(|pair| factors_conflict(pair.first_factor, pair.second_factor, tolerance))
+            .map(|pair| ConversionConflict {
+                first_id: pair.first_id,
+                second_id: pair.second_id,
+                from_unit_id: pair.from_unit_id,
+                to_unit_id: pair.to_unit_id,
+                first_factor: pair.first_factor,
+                second_factor: pair.second_factor,
+            })
+            .collect();
+
+        Ok(Json(conflicts))
+    }
+
+    /// Unit Conversions: Fetch by id
+    #[oai(path = "/unit_conversions/:id", method = "get")]
+    async fn get_unit_conversion(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<UnitConversion>> {
+        let unit_conversion: Option<UnitConversion> = sqlx::query_as!(
+            UnitConversion,
+            "SELECT * FROM unit_conversions WHERE id = $1",
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match unit_conversion {
+            Some(uc) => Ok(GetResponse::Success(Json(uc))),
+            None => Ok(GetResponse::NotFound(PlainText(
+                format!("No unit conversion with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    /// Unit Conversions: Create new
+    #[oai(path = "/unit_conversions", method = "post")]
+    async fn new_unit_conversion(
+        &self,
+        pool: Data<&PgPool>,
+        conversion: Json<UnitConversion>,
+    ) -> Result<ConversionCreateResponse> {
+        if conversion.from_unit_id == conversion.to_unit_id {
+            return Ok(ConversionCreateResponse::BadRequest(PlainText(format!(
+                "Can't convert unit '{}' to itself.",
+                conversion.from_unit_id
+            ))));
+        }
+        if let Some(id) =
+            existing_unit_conversion(pool.0, conversion.from_unit_id, conversion.to_unit_id).await?
+        {
+            return Ok(ConversionCreateResponse::Conflict(PlainText(format!(
+                "Unit conversion '{id}' already converts unit '{}' to unit '{}'.",
+                conversion.from_unit_id, conversion.to_unit_id
+            ))));
+        }
+        if let Some(id) = contradicting_unit_conversion(
+            pool.0,
+            conversion.from_unit_id,
+            conversion.to_unit_id,
+            conversion.factor,
+        )
+        .await?
+        {
+            return Ok(ConversionCreateResponse::Conflict(PlainText(format!(
+                "Unit conversion '{id}' converts unit '{}' to unit '{}' with a contradicting factor.",
+                conversion.to_unit_id, conversion.from_unit_id
+            ))));
+        }
+        if let Some(reason) = inconsistent_cycle(
+            pool.0,
+            conversion.from_unit_id,
+            conversion.to_unit_id,
+            conversion.factor,
+        )
+        .await?
+        {
+            return Ok(ConversionCreateResponse::Unprocessable(PlainText(reason)));
+        }
+
+        let record = sqlx::query!(
+            r#"
+INSERT INTO unit_conversions (from_unit_id, to_unit_id, factor)
+VALUES ($1, $2, $3)
+RETURNING id"#,
+            conversion.from_unit_id,
+            conversion.to_unit_id,
+            conversion.factor,
+        )
+        .fetch_one(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(ConversionCreateResponse::Success(Json(record.id)))
+    }
+
+    /// Unit Conversions: Update with id
+    ///
+    /// The new units and factor are checked like those of a new conversion. Deleted conversions
+    /// have to be restored first.
+    #[oai(path = "/unit_conversions/:id", method = "put")]
+    async fn replace_unit_conversion(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        unit_conversion: Json<UnitConversion>,
+    ) -> Result<EditResponse<UnitConversion>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let response = update_unit_conversion(&mut tx, id.0, &unit_conversion).await?;
+        if let EditResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Unit Conversions: Update some fields with id
+    #[oai(path = "/unit_conversions/:id", method = "patch")]
+    async fn patch_unit_conversion(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        patch: Json<UnitConversionPatch>,
+    ) -> Result<EditResponse<UnitConversion>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let unit_conversion = sqlx::query_as!(
+            UnitConversion,
+            r#"
+SELECT * FROM unit_conversions WHERE id = $1 AND deleted_at IS NULL FOR UPDATE"#,
+            id.0
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let Some(mut unit_conversion) = unit_conversion else {
+            return Ok(EditResponse::NotFound(PlainText(format!(
+                "No unit conversion with id '{}' found.",
+                id.0
+            ))));
+        };
+        let patch = patch.0;
+        if let Some(from_unit_id) = patch.from_unit_id {
+            unit_conversion.from_unit_id = from_unit_id;
+        }
+        if let Some(to_unit_id) = patch.to_unit_id {
+            unit_conversion.to_unit_id = to_unit_id;
+        }
+        patch.factor.update_to(&mut unit_conversion.factor);
+
+        let response = update_unit_conversion(&mut tx, id.0, &unit_conversion).await?;
+        if let EditResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Unit Conversions: Delete with id
+    ///
+    /// The conversion is only marked as deleted, and can be restored through
+    /// `/unit_conversions/:id/restore`.
+    #[oai(path = "/unit_conversions/:id", method = "delete")]
+    async fn delete_unit_conversion(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// Respond with `204 No Content` instead of echoing the deleted id
+        no_content: Query<Option<bool>>,
+    ) -> Result<DeleteResponse> {
+        let result = sqlx::query!(
+            r#"
+UPDATE unit_conversions
+SET deleted_at = LOCALTIMESTAMP
+WHERE id = $1 AND deleted_at IS NULL
+RETURNING id"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(_) => Ok(DeleteResponse::deleted(id.0, no_content.0)),
+            None => Ok(DeleteResponse::NotFound(PlainText(
+                format!("No unit conversion with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    /// Unit Conversions: Restore a deleted conversion
+    ///
+    /// The conversion is checked like a new one, and isn't restored if it would conflict with
+    /// the conversions added since it was deleted.
+    #[oai(path = "/unit_conversions/:id/restore", method = "post")]
+    async fn restore_unit_conversion(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<RestoreResponse> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let response = restore_unit_conversion(&mut tx, id.0).await?;
+        if let RestoreResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    // PLACES
+    /// Places: Fetch all
+    #[oai(path = "/places", method = "get")]
+    async fn get_places(&self, pool: Data<&PgPool>) -> Result<GetAllResponse<Place>> {
+        let places = sqlx::query_as!(Place, "SELECT * FROM places")
+            .fetch_all(pool.0)
+            .await
+            .map_err(InternalServerError)?;
+
+        Ok(Json(places))
+    }
+
+    /// Places: Fetch by id
+    #[oai(path = "/places/:id", method = "get")]
+    async fn get_place(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<GetResponse<Place>> {
+        let result: Option<Place> =
+            sqlx::query_as!(Place, "SELECT * FROM places WHERE id = $1", id.0)
+                .fetch_optional(pool.0)
+                .await
+                .map_err(InternalServerError)?;
+
+        match result {
+            Some(place) => Ok(GetResponse::Success(Json(place))),
+            None => Ok(GetResponse::NotFound(PlainText(
+                format!("No place with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    /// Places: Fetch stock entries
+    ///
+    /// Lists every stock entry referencing the place, whatever its status, with a summary of the
+    /// spend on its confirmed purchases.
+    #[oai(path = "/places/:id/entries", method = "get")]
+    async fn get_place_entries(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<PlaceActivity>> {
+        let place = sqlx::query_scalar!("SELECT id FROM places WHERE id = $1", id.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?;
+        if place.is_none() {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No place with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let records = sqlx::query!(
+            r#"
+SELECT e.id, e.entry_timestamp, e.entry_type AS "entry_type: EntryType", e.stock_quantity,
+    e.stock_item_id, e.product_id, e.place_id, e.to_space_id, e.price, e.memo,
+    e.status AS "status: EntryStatus", p.name AS "product_name?"
+FROM stock_entries e
+LEFT JOIN stock_items si ON si.id = e.stock_item_id
+LEFT JOIN products p ON p.id = COALESCE(e.product_id, si.product_id)
+WHERE e.place_id = $1
+ORDER BY e.entry_timestamp, e.id"#,
+            id.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut purchase_count = 0;
+        let mut unpriced_purchase_count = 0;
+        let mut total_spend = 0.0;
+        let entries = records
+            .into_iter()
+            .map(|record| {
+                if matches!(record.entry_type, EntryType::Purchase)
+                    && record.status == EntryStatus::Confirmed
+                {
+                    purchase_count += 1;
+                    match record.price {
+                        Some(price) => {
+                            total_spend += f64::from(record.stock_quantity) * f64::from(price)
+                        }
+                        None => unpriced_purchase_count += 1,
+                    }
+                }
+
+                PlaceEntry {
+                    entry: StockEntry {
+                        id: record.id.into(),
+                        entry_timestamp: record.entry_timestamp,
+                        entry_type: record.entry_type,
+                        stock_quantity: record.stock_quantity,
+                        stock_item_id: record.stock_item_id,
+                        product_id: record.product_id,
+                        place_id: record.place_id,
+                        to_space_id: record.to_space_id,
+                        price: record.price,
+                        memo: record.memo,
+                        status: record.status,
+                    },
+                    product_name: record.product_name,
+                }
+            })
+            .collect();
+
+        Ok(GetResponse::Success(Json(PlaceActivity {
+            place_id: id.0,
+            entries,
+            purchase_count,
+            unpriced_purchase_count,
+            total_spend,
+        })))
+    }
+
+    /// Places: Create new
+    #[oai(path = "/place", method = "post")]
+    async fn new_place(&self, pool: Data<&PgPool>, place: Json<Place>) -> Result<Json<i32>> {
+        let record = sqlx::query!(
+            r#"
+INSERT INTO places (name, description)
+VALUES ($1, $2)
+RETURNING id"#,
+            place.name,
+            place.description,
+        )
+        .fetch_one(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(Json(record.id))
+    }
+
+    /// Places: Update with id
+    #[oai(path = "/places/:id", method = "put")]
+    async fn replace_place(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        place: Json<Place>,
+    ) -> Result<UpdateResponse<Place>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let response = update_place(&mut tx, id.0, &place).await?;
+        if let UpdateResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Places: Update some fields with id
+    #[oai(path = "/places/:id", method = "patch")]
+    async fn patch_place(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        patch: Json<PlacePatch>,
+    ) -> Result<UpdateResponse<Place>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let place = sqlx::query_as!(
+            Place,
+            r#"
+SELECT * FROM places WHERE id = $1 FOR UPDATE"#,
+            id.0
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let Some(mut place) = place else {
+            return Ok(UpdateResponse::NotFound(PlainText(format!(
+                "No place with id '{}' found.",
+                id.0
+            ))));
+        };
+        let patch = patch.0;
+        if let Some(name) = patch.name {
+            place.name = name;
+        }
+        patch.description.update_to(&mut place.description);
+
+        let response = update_place(&mut tx, id.0, &place).await?;
+        if let UpdateResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Places: Delete with id
+    #[oai(path = "/places/:id", method = "delete")]
+    async fn delete_place(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// Respond with `204 No Content` instead of echoing the deleted id
+        no_content: Query<Option<bool>>,
+    ) -> Result<DeleteResponse> {
+        let result = sqlx::query!(
+            r#"
+DELETE FROM places
+WHERE id = $1
+RETURNING id"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(_) => Ok(DeleteResponse::deleted(id.0, no_content.0)),
+            None => Ok(DeleteResponse::NotFound(PlainText(
+                format!("No place with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    // SPACES
+    /// Spaces: Fetch all
+    #[oai(path = "/spaces", method = "get")]
+    async fn get_spaces(&self, pool: Data<&PgPool>) -> Result<GetAllResponse<Space>> {
+        let spaces = sqlx::query_as!(Space, "SELECT * FROM spaces")
+            .fetch_all(pool.0)
+            .await
+            .map_err(InternalServerError)?;
+
+        Ok(Json(spaces))
+    }
+
+    /// Spaces: Fetch by id
+    #[oai(path = "/spaces/:id", method = "get")]
+    async fn get_space(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<GetResponse<Space>> {
+        let result: Option<Space> =
+            sqlx::query_as!(Space, "SELECT * FROM spaces WHERE id = $1", id.0)
+                .fetch_optional(pool.0)
+                .await
+                .map_err(InternalServerError)?;
+
+        match result {
+            Some(space) => Ok(GetResponse::Success(Json(space))),
+            None => Ok(GetResponse::NotFound(PlainText(
+                format!("No space with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    /// Spaces: Fetch the days until each product runs out
+    ///
+    /// Divides the stock of every product in the space by the rate it was consumed from the
+    /// space at, soonest to run out first.
+    #[oai(path = "/spaces/:id/days_remaining", method = "get")]
+    async fn get_space_days_remaining(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// The number of days to average consumption over (defaults to 30)
+        #[oai(validator(minimum(value = "1"), maximum(value = "3650")))]
+        days: Query<Option<i32>>,
+    ) -> Result<GetResponse<Vec<SpaceRunway>>> {
+        let days = days.0.unwrap_or(30);
+        let exists = sqlx::query_scalar!("SELECT id FROM spaces WHERE id = $1", id.0)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?
+            .is_some();
+
+        if !exists {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No space with id '{}' found.",
+                id.0
+            ))));
+        }
+
+        let records = sqlx::query!(
+            r#"
+WITH stock AS (
+    SELECT product_id, SUM(stock_quantity)::float8 AS quantity
+    FROM stock_items
+    WHERE space_id = $1
+    GROUP BY product_id
+    HAVING SUM(stock_quantity) > 0
+), consumption AS (
+    SELECT si.product_id, SUM(e.stock_quantity)::float8 AS quantity
+    FROM stock_entries e
+    JOIN stock_items si ON si.id = e.stock_item_id
+    WHERE si.space_id = $1 AND e.entry_type = 'consume' AND e.status = 'confirmed'
+      AND e.entry_timestamp >= LOCALTIMESTAMP - make_interval(days => $2)
+    GROUP BY si.product_id
+)
+SELECT p.id, p.name, s.quantity AS "stock_quantity!", COALESCE(c.quantity, 0) AS "consumed!"
+FROM stock s
+JOIN products p ON p.id = s.product_id
+LEFT JOIN consumption c ON c.product_id = s.product_id
+ORDER BY p.id"#,
+            id.0,
+            days
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut products: Vec<SpaceRunway> = records
+            .into_iter()
+            .map(|record| {
+                let daily_rate = record.consumed / f64::from(days);
+                SpaceRunway {
+                    product_id: record.id,
+                    name: record.name,
+                    stock_quantity: record.stock_quantity,
+                    daily_rate,
+                    days_remaining: (daily_rate > 0.0).then(|| record.stock_quantity / daily_rate),
+                }
+            })
+            .collect();
+        // Products that aren't being consumed never run out
+        products.sort_by(|a, b| {
+            let days_remaining =
+                |runway: &SpaceRunway| runway.days_remaining.unwrap_or(f64::INFINITY);
+            days_remaining(a).total_cmp(&days_remaining(b))
+        });
+
+        Ok(GetResponse::Success(Json(products)))
+    }
+
+    /// Spaces: Create new
+    #[oai(path = "/space", method = "post")]
+    async fn new_space(&self, pool: Data<&PgPool>, space: Json<Space>) -> Result<Json<i32>> {
+        let record = sqlx::query!(
+            r#"
+INSERT INTO spaces (name, description)
+VALUES ($1, $2)
+RETURNING id"#,
+            space.name,
+            space.description,
+        )
+        .fetch_one(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(Json(record.id))
+    }
+
+    /// Spaces: Compare the contents of two spaces
+    ///
+    /// Sorts the products in stock in either space by whether they are in space `a`, space `b` or
+    /// both, ordered by name.
+    #[oai(path = "/spaces/compare", method = "get")]
+    async fn compare_spaces(
+        &self,
+        pool: Data<&PgPool>,
+        /// The id of the first space
+        a: Query<i32>,
+        /// The id of the second space
+        b: Query<i32>,
+    ) -> Result<GetResponse<SpaceComparison>> {
+        for id in [a.0, b.0] {
+            let exists = sqlx::query_scalar!("SELECT id FROM spaces WHERE id = $1", id)
+                .fetch_optional(pool.0)
+                .await
+                .map_err(InternalServerError)?
+                .is_some();
+
+            if !exists {
+                return Ok(GetResponse::NotFound(PlainText(format!(
+                    "No space with id '{id}' found."
+                ))));
+            }
+        }
+
+        let products = sqlx::query_as!(
+            ComparedProduct,
+            r#"
+WITH a AS (
+    SELECT product_id, SUM(stock_quantity)::float8 AS quantity
+    FROM stock_items
+    WHERE space_id = $1
+    GROUP BY product_id
+    HAVING SUM(stock_quantity) > 0
+), b AS (
+    SELECT product_id, SUM(stock_quantity)::float8 AS quantity
+    FROM stock_items
+    WHERE space_id = $2
+    GROUP BY product_id
+    HAVING SUM(stock_quantity) > 0
+)
+SELECT p.id AS product_id, p.name, a.quantity AS a_quantity, b.quantity AS b_quantity
+FROM a
+FULL OUTER JOIN b ON b.product_id = a.product_id
+JOIN products p ON p.id = COALESCE(a.product_id, b.product_id)
+ORDER BY p.name"#,
+            a.0,
+            b.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut comparison = SpaceComparison {
+            only_a: Vec::new(),
+            only_b: Vec::new(),
+            both: Vec::new(),
+        };
+        for product in products {
+            match (product.a_quantity, product.b_quantity) {
+                (Some(_), Some(_)) => comparison.both.push(product),
+                (Some(_), None) => comparison.only_a.push(product),
+                _ => comparison.only_b.push(product),
+            }
+        }
+
+        Ok(GetResponse::Success(Json(comparison)))
+    }
+
+    /// Spaces: Update with id
+    #[oai(path = "/spaces/:id", method = "put")]
+    async fn replace_space(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        space: Json<Space>,
+    ) -> Result<UpdateResponse<Space>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let response = update_space(&mut tx, id.0, &space).await?;
+        if let UpdateResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Spaces: Update some fields with id
+    #[oai(path = "/spaces/:id", method = "patch")]
+    async fn patch_space(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        patch: Json<SpacePatch>,
+    ) -> Result<UpdateResponse<Space>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let space = sqlx::query_as!(
+            Space,
+            r#"
+SELECT * FROM spaces WHERE id = $1 FOR UPDATE"#,
+            id.0
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let Some(mut space) = space else {
+            return Ok(UpdateResponse::NotFound(PlainText(format!(
+                "No space with id '{}' found.",
+                id.0
+            ))));
+        };
+        let patch = patch.0;
+        if let Some(name) = patch.name {
+            space.name = name;
+        }
+        patch.description.update_to(&mut space.description);
+
+        let response = update_space(&mut tx, id.0, &space).await?;
+        if let UpdateResponse::Success(_) = response {
+            tx.commit().await.map_err(InternalServerError)?;
+        }
+
+        Ok(response)
+    }
+
+    /// Spaces: Delete with id
+    #[oai(path = "/spaces/:id", method = "delete")]
+    async fn delete_space(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// Respond with `204 No Content` instead of echoing the deleted id
+        no_content: Query<Option<bool>>,
+    ) -> Result<DeleteResponse> {
+        let result = sqlx::query!(
+            r#"
+DELETE FROM spaces
+WHERE id = $1
+RETURNING id"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(_) => Ok(DeleteResponse::deleted(id.0, no_content.0)),
+            None => Ok(DeleteResponse::NotFound(PlainText(
+                format!("No space with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    // STOCK ITEMS
+    /// Stock Items: Fetch all
+    #[oai(path = "/stock_items", method = "get")]
+    async fn get_stock_items(
+        &self,
+        pool: Data<&PgPool>,
+        /// The comma-separated related resources to embed under `_expanded`
+        #[oai(default, explode = false)]
+        expand: Query<Vec<StockItemRelation>>,
+    ) -> Result<GetAllResponse<ExpandedStockItem>> {
+        let items = sqlx::query_as!(
+            StockItem,
+            r#"
+SELECT si.id AS "id!", si.product_id AS "product_id!", si.space_id AS "space_id!",
+    si.best_by_date,
+    COALESCE(round(si.stock_quantity::numeric, u.decimal_places)::real, si.stock_quantity) AS "stock_quantity!"
+FROM stock_items si
+LEFT JOIN products p ON p.id = si.product_id
+LEFT JOIN units u ON u.id = p.stock_unit_id"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(Json(expand_stock_items(pool.0, items, &expand).await?))
+    }
+
+    /// Stock Items: Fetch by id
+    #[oai(path = "/stock_items/:id", method = "get")]
+    async fn get_stock_item(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// The comma-separated related resources to embed under `_expanded`
+        #[oai(default, explode = false)]
+        expand: Query<Vec<StockItemRelation>>,
+    ) -> Result<GetResponse<ExpandedStockItem>> {
+        let result: Option<StockItem> = sqlx::query_as!(
+            StockItem,
+            r#"
+SELECT si.id AS "id!", si.product_id AS "product_id!", si.space_id AS "space_id!",
+    si.best_by_date,
+    COALESCE(round(si.stock_quantity::numeric, u.decimal_places)::real, si.stock_quantity) AS "stock_quantity!"
+FROM stock_items si
+LEFT JOIN products p ON p.id = si.product_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+WHERE si.id = $1"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(item) => {
+                let mut items = expand_stock_items(pool.0, vec![item], &expand).await?;
+                Ok(GetResponse::Success(Json(items.remove(0))))
+            }
+            None => Ok(GetResponse::NotFound(PlainText(
+                format!("No stock item with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    /// Stock Items: Fetch all, with details
+    #[oai(path = "/stock_items/details", method = "get")]
+    async fn get_stock_item_details(
+        &self,
+        pool: Data<&PgPool>,
+    ) -> Result<GetAllResponse<StockItemDetail>> {
+        let items = sqlx::query_as!(
+            StockItemDetail,
+            r#"
+SELECT si.id, si.product_id, p.name AS product_name, si.space_id, s.name AS space_name,
+    COALESCE(round(si.stock_quantity::numeric, u.decimal_places)::real, si.stock_quantity) AS "stock_quantity!",
+    p.stock_unit_id,
+    si.stock_quantity / NULLIF(p.purchase_to_stock_factor, 0) AS purchase_unit_quantity,
+    p.purchase_unit_id,
+    si.best_by_date,
+    format_quantity(
+        COALESCE(round(si.stock_quantity::numeric, u.decimal_places)::real, si.stock_quantity),
+        u.singular,
+        u.plural
+    ) AS display
+FROM stock_items si
+JOIN products p ON p.id = si.product_id
+JOIN spaces s ON s.id = si.space_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+ORDER BY si.id"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(Json(items))
+    }
+
+    /// Stock Items: Search by product name, with details
+    #[oai(path = "/stock_items/search", method = "get")]
+    async fn search_stock_items(
+        &self,
+        pool: Data<&PgPool>,
+        /// The text the product name should contain, ignoring case
+        product_name: Query<String>,
+        /// Only search the stock items in this space
+        space_id: Query<Option<i32>>,
+    ) -> Result<GetAllResponse<StockItemDetail>> {
+        let items = sqlx::query_as!(
+            StockItemDetail,
+            r#"
+SELECT si.id, si.product_id, p.name AS product_name, si.space_id, s.name AS space_name,
+    COALESCE(round(si.stock_quantity::numeric, u.decimal_places)::real, si.stock_quantity) AS "stock_quantity!",
+    p.stock_unit_id,
+    si.stock_quantity / NULLIF(p.purchase_to_stock_factor, 0) AS purchase_unit_quantity,
+    p.purchase_unit_id,
+    si.best_by_date,
+    format_quantity(
+        COALESCE(round(si.stock_quantity::numeric, u.decimal_places)::real, si.stock_quantity),
+        u.singular,
+        u.plural
+    ) AS display
+FROM stock_items si
+JOIN products p ON p.id = si.product_id
+JOIN spaces s ON s.id = si.space_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+WHERE p.name ILIKE $1 AND ($2::int IS NULL OR si.space_id = $2)
+ORDER BY p.name, si.id"#,
+            like_pattern(&product_name.0),
+            space_id.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(Json(items))
+    }
+
+    /// Stock Items: Fetch by id, with details
+    #[oai(path = "/stock_items/:id/details", method = "get")]
+    async fn get_stock_item_detail(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<StockItemDetail>> {
+        let result = sqlx::query_as!(
+            StockItemDetail,
+            r#"
+SELECT si.id, si.product_id, p.name AS product_name, si.space_id, s.name AS space_name,
+    COALESCE(round(si.stock_quantity::numeric, u.decimal_places)::real, si.stock_quantity) AS "stock_quantity!",
+    p.stock_unit_id,
+    si.stock_quantity / NULLIF(p.purchase_to_stock_factor, 0) AS purchase_unit_quantity,
+    p.purchase_unit_id,
+    si.best_by_date,
+    format_quantity(
+        COALESCE(round(si.stock_quantity::numeric, u.decimal_places)::real, si.stock_quantity),
+        u.singular,
+        u.plural
+    ) AS display
+FROM stock_items si
+JOIN products p ON p.id = si.product_id
+JOIN spaces s ON s.id = si.space_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+WHERE si.id = $1"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(item) => Ok(GetResponse::Success(Json(item))),
+            None => Ok(GetResponse::NotFound(PlainText(format!(
+                "No stock item with id '{}' found.",
+                id.0
+            )))),
+        }
+    }
+
+    /// Stock Items: Create new
+    #[oai(path = "/stock_item", method = "post")]
+    async fn new_stock_item(
+        &self,
+        pool: Data<&PgPool>,
+        item: Json<StockItem>,
+    ) -> Result<CreateResponse> {
+        if let Some(reason) =
+            check_discrete_quantity(pool.0, item.product_id, item.stock_quantity).await?
+        {
+            return Ok(CreateResponse::Unprocessable(PlainText(reason)));
+        }
+
+        let record = sqlx::query!(
+            r#"
+INSERT INTO stock_items (product_id, space_id, stock_quantity, best_by_date)
+VALUES ($1, $2, $3, $4)
+RETURNING id"#,
+            item.product_id,
+            item.space_id,
+            item.stock_quantity,
+            item.best_by_date,
+        )
+        .fetch_one(pool.0)
+        .await;
+
+        match record {
+            Ok(record) => {
+                self.stock_totals.request();
+                self.publish_stock_changes(vec![StockChange {
+                    stock_item_id: record.id,
+                    product_id: item.product_id,
+                    space_id: item.space_id,
+                    stock_quantity: item.stock_quantity,
+                }]);
+                Ok(CreateResponse::Success(Json(record.id)))
+            }
+            Err(err) if is_unique_violation(&err) => Ok(CreateResponse::Conflict(PlainText(format!(
+                "A stock item of product '{}' in space '{}' with the same best by date already exists.",
+                item.product_id, item.space_id
+            )))),
+            Err(err) => Err(InternalServerError(err)),
+        }
+    }
+
+    /// Stock Items: Create or update by product, space and best by date
+    #[oai(path = "/stock_items", method = "put")]
+    async fn upsert_stock_item(
+        &self,
+        pool: Data<&PgPool>,
+        item: Json<StockItem>,
+    ) -> Result<UpsertResponse> {
+        if let Some(reason) =
+            check_discrete_quantity(pool.0, item.product_id, item.stock_quantity).await?
+        {
+            return Ok(UpsertResponse::Unprocessable(PlainText(reason)));
+        }
+
+        let record = sqlx::query!(
+            r#"
+INSERT INTO stock_items (product_id, space_id, stock_quantity, best_by_date)
+VALUES ($1, $2, $3, $4)
+ON CONFLICT (product_id, space_id, best_by_date)
+DO UPDATE SET stock_quantity = EXCLUDED.stock_quantity
+RETURNING id, xmax = 0 AS "created!""#,
+            item.product_id,
+            item.space_id,
+            item.stock_quantity,
+            item.best_by_date,
+        )
+        .fetch_one(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+        self.stock_totals.request();
+        self.publish_stock_changes(vec![StockChange {
+            stock_item_id: record.id,
+            product_id: item.product_id,
+            space_id: item.space_id,
+            stock_quantity: item.stock_quantity,
+        }]);
+
+        Ok(UpsertResponse::Success(Json(UpsertResult {
+            id: record.id,
+            created: record.created,
+        })))
+    }
+
+    /// Stock Items: Update with id
+    ///
+    /// A changed quantity is recorded as an `Adjust` entry for the difference.
+    #[oai(path = "/stock_items/:id", method = "put")]
+    async fn replace_stock_item(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        stock_item: Json<StockItem>,
+    ) -> Result<EditResponse<StockItem>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let response = update_stock_item(&mut tx, id.0, &stock_item).await?;
+        if let EditResponse::Success(Json(item)) = &response {
+            tx.commit().await.map_err(InternalServerError)?;
+            self.stock_totals.request();
+            self.publish_stock_changes(vec![StockChange {
+                stock_item_id: id.0,
+                product_id: item.product_id,
+                space_id: item.space_id,
+                stock_quantity: item.stock_quantity,
+            }]);
+        }
+
+        Ok(response)
+    }
+
+    /// Stock Items: Update some fields with id
+    ///
+    /// A changed quantity is recorded as an `Adjust` entry for the difference.
+    #[oai(path = "/stock_items/:id", method = "patch")]
+    async fn patch_stock_item(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        patch: Json<StockItemPatch>,
+    ) -> Result<EditResponse<StockItem>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let stock_item = sqlx::query_as!(
+            StockItem,
+            r#"
+SELECT id, product_id, space_id, stock_quantity, best_by_date FROM stock_items WHERE id = $1 FOR UPDATE"#,
+            id.0
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let Some(mut stock_item) = stock_item else {
+            return Ok(EditResponse::NotFound(PlainText(format!(
+                "No stock item with id '{}' found.",
+                id.0
+            ))));
+        };
+        let patch = patch.0;
+        if let Some(product_id) = patch.product_id {
+            stock_item.product_id = product_id;
+        }
+        if let Some(space_id) = patch.space_id {
+            stock_item.space_id = space_id;
+        }
+        if let Some(stock_quantity) = patch.stock_quantity {
+            stock_item.stock_quantity = stock_quantity;
+        }
+        patch.best_by_date.update_to(&mut stock_item.best_by_date);
+
+        let response = update_stock_item(&mut tx, id.0, &stock_item).await?;
+        if let EditResponse::Success(Json(item)) = &response {
+            tx.commit().await.map_err(InternalServerError)?;
+            self.stock_totals.request();
+            self.publish_stock_changes(vec![StockChange {
+                stock_item_id: id.0,
+                product_id: item.product_id,
+                space_id: item.space_id,
+                stock_quantity: item.stock_quantity,
+            }]);
+        }
+
+        Ok(response)
+    }
+
+    /// Stock Items: Delete with id
+    #[oai(path = "/stock_item/:id", method = "delete")]
+    async fn delete_stock_item(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// Respond with `204 No Content` instead of echoing the deleted id
+        no_content: Query<Option<bool>>,
+    ) -> Result<DeleteResponse> {
+        let result = sqlx::query!(
+            r#"
+WITH deleted AS (
+    DELETE FROM stock_items
+    WHERE id = $1
+    RETURNING id, product_id, space_id, best_by_date
+)
+INSERT INTO deleted_stock_items (id, product_id, space_id, best_by_date)
+SELECT id, product_id, space_id, best_by_date FROM deleted
+RETURNING product_id, space_id"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(item) => {
+                self.stock_totals.request();
+                self.publish_stock_changes(vec![StockChange {
+                    stock_item_id: id.0,
+                    product_id: item.product_id,
+                    space_id: item.space_id,
+                    stock_quantity: 0.0,
+                }]);
+                Ok(DeleteResponse::deleted(id.0, no_content.0))
+            }
+            None => Ok(DeleteResponse::NotFound(PlainText(
+                format!("No stock item with id '{}' found.", id.0),
+            ))),
+        }
+    }
+
+    // STOCK ENTRIES
+    /// Stock Entries: Fetch all
+    #[oai(path = "/stock_entries", method = "get")]
+    async fn get_stock_entries(
+        &self,
+        pool: Data<&PgPool>,
+        /// Only fetch entries with this status
+        status: Query<Option<EntryStatus>>,
+    ) -> Result<GetAllResponse<StockEntry>> {
+        let entries = sqlx::query_as!(
+            StockEntry,
+            r#"
+SELECT id, entry_timestamp, entry_type AS "entry_type: EntryType", stock_quantity,
+    stock_item_id, product_id, place_id, to_space_id, price, memo,
+    status AS "status: EntryStatus"
+FROM stock_entries
+WHERE $1::entry_status IS NULL OR status = $1
+ORDER BY entry_timestamp"#,
+            status.0 as Option<EntryStatus>
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(Json(entries))
+    }
+
+    /// Stock Entries: Fetch by id
+    #[oai(path = "/stock_entries/:id", method = "get")]
+    async fn get_stock_entry(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<GetResponse<StockEntry>> {
+        let result = sqlx::query_as!(
+            StockEntry,
+            r#"
+SELECT id, entry_timestamp, entry_type AS "entry_type: EntryType", stock_quantity,
+    stock_item_id, product_id, place_id, to_space_id, price, memo,
+    status AS "status: EntryStatus"
+FROM stock_entries
+WHERE id = $1"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(entry) => Ok(GetResponse::Success(Json(entry))),
+            None => Ok(GetResponse::NotFound(PlainText(format!(
+                "No stock entry with id '{}' found.",
+                id.0
+            )))),
+        }
+    }
+
+    /// Stock Entries: Create new
+    ///
+    /// The entry has to pass the checks of `/stock_entries/validate`. Confirmed entries are
+    /// applied to stock right away, in the same transaction, while pending entries are applied
+    /// once they're confirmed. Returns the recorded entry.
+    #[oai(path = "/stock_entries", method = "post")]
+    async fn new_stock_entry(
+        &self,
+        pool: Data<&PgPool>,
+        entry: Json<StockEntry>,
+    ) -> Result<EntryCreateResponse> {
+        let memo = match normalize_memo(entry.memo.as_deref(), self.max_memo_length) {
+            Ok(memo) => memo,
+            Err(reason) => return Ok(EntryCreateResponse::BadRequest(PlainText(reason))),
+        };
+        let errors = validate_stock_entry(pool.0, &entry, self.max_memo_length).await?;
+        if !errors.is_empty() {
+            return Ok(EntryCreateResponse::Invalid(Json(errors)));
+        }
+
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let entry = sqlx::query_as!(
+            StockEntry,
+            r#"
+INSERT INTO stock_entries (entry_type, stock_quantity, stock_item_id, product_id, place_id,
+    to_space_id, price, memo, status)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+RETURNING id, entry_timestamp, entry_type AS "entry_type: EntryType", stock_quantity,
+    stock_item_id, product_id, place_id, to_space_id, price, memo,
+    status AS "status: EntryStatus""#,
+            entry.entry_type as EntryType,
+            entry.stock_quantity,
+            entry.stock_item_id,
+            entry.product_id,
+            entry.place_id,
+            entry.to_space_id,
+            entry.price,
+            memo,
+            entry.status as EntryStatus
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        if entry.status == EntryStatus::Pending {
+            tx.commit().await.map_err(InternalServerError)?;
+            return Ok(EntryCreateResponse::Success(Json(entry)));
+        }
+        let changes = match apply_stock_entry(&mut tx, &entry).await? {
+            Ok(changes) => changes,
+            Err(reason) => {
+                return Ok(EntryCreateResponse::Invalid(Json(vec![FieldError {
+                    field: "stock_item_id".to_string(),
+                    message: reason,
+                }])))
+            }
+        };
+        tx.commit().await.map_err(InternalServerError)?;
+        self.stock_totals.request();
+        self.publish_stock_changes(changes);
+
+        Ok(EntryCreateResponse::Success(Json(entry)))
+    }
+
+    /// Stock Entries: Delete with id
+    ///
+    /// Only pending entries can be deleted. Confirmed entries have been applied to stock, and
+    /// are corrected by recording an `Adjust` entry instead.
+    #[oai(path = "/stock_entries/:id", method = "delete")]
+    async fn delete_stock_entry(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+        /// Respond with `204 No Content` instead of echoing the deleted id
+        no_content: Query<Option<bool>>,
+    ) -> Result<EntryDeleteResponse> {
+        let result = sqlx::query!(
+            r#"
+WITH deleted AS (
+    DELETE FROM stock_entries
+    WHERE id = $1 AND status = 'pending'
+    RETURNING id
+)
+SELECT EXISTS (SELECT 1 FROM deleted) AS "deleted!"
+FROM stock_entries
+WHERE id = $1"#,
+            id.0
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        match result {
+            Some(record) if record.deleted => {
+                if no_content.0.unwrap_or(false) {
+                    Ok(EntryDeleteResponse::NoContent)
+                } else {
+                    Ok(EntryDeleteResponse::Success(Json(id.0)))
+                }
+            }
+            Some(_) => Ok(EntryDeleteResponse::Conflict(PlainText(format!(
+                "Stock entry '{}' is confirmed and can't be deleted.",
+                id.0
+            )))),
+            None => Ok(EntryDeleteResponse::NotFound(PlainText(format!(
+                "No stock entry with id '{}' found.",
+                id.0
+            )))),
+        }
+    }
+
+    /// Stock Entries: Validate a draft entry
+    ///
+    /// Runs the checks an entry has to pass without recording it.
+    #[oai(
+        path = "/stock_entries/validate",
+        method = "post",
+        tag = "ApiTags::Reading"
+    )]
+    async fn validate_stock_entry(
+        &self,
+        pool: Data<&PgPool>,
+        entry: Json<StockEntry>,
+    ) -> Result<Json<EntryValidation>> {
+        let errors = validate_stock_entry(pool.0, &entry, self.max_memo_length).await?;
+
+        Ok(Json(EntryValidation {
+            valid: errors.is_empty(),
+            errors,
+        }))
+    }
+
+    /// Stock Entries: Fetch entries with dangling references
+    ///
+    /// Lists the entries referencing a stock item, product, place or space that no longer exists.
+    /// Stock items deleted through the API don't count, their entries are kept on purpose.
+    #[oai(path = "/stock_entries/dangling", method = "get")]
+    async fn get_dangling_stock_entries(
+        &self,
+        pool: Data<&PgPool>,
+    ) -> Result<GetAllResponse<DanglingStockEntry>> {
+        let records = sqlx::query!(
+            r#"
+SELECT e.id, e.entry_timestamp, e.entry_type AS "entry_type: EntryType", e.stock_quantity,
+    e.stock_item_id, e.product_id, e.place_id, e.to_space_id, e.price, e.memo,
+    e.status AS "status: EntryStatus",
+    e.stock_item_id IS NOT NULL AND si.id IS NULL AND d.id IS NULL AS "dangling_stock_item!",
+    e.product_id IS NOT NULL AND p.id IS NULL AS "dangling_product!",
+    e.place_id IS NOT NULL AND pl.id IS NULL AS "dangling_place!",
+    e.to_space_id IS NOT NULL AND s.id IS NULL AS "dangling_to_space!"
+FROM stock_entries e
+LEFT JOIN stock_items si ON si.id = e.stock_item_id
+LEFT JOIN deleted_stock_items d ON d.id = e.stock_item_id
+LEFT JOIN products p ON p.id = e.product_id
+LEFT JOIN places pl ON pl.id = e.place_id
+LEFT JOIN spaces s ON s.id = e.to_space_id
+WHERE (e.stock_item_id IS NOT NULL AND si.id IS NULL AND d.id IS NULL)
+   OR (e.product_id IS NOT NULL AND p.id IS NULL)
+   OR (e.place_id IS NOT NULL AND pl.id IS NULL)
+   OR (e.to_space_id IS NOT NULL AND s.id IS NULL)
+ORDER BY e.entry_timestamp"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let entries = records
+            .into_iter()
+            .map(|record| {
+                let dangling_fields = [
+                    (record.dangling_stock_item, "stock_item_id"),
+                    (record.dangling_product, "product_id"),
+                    (record.dangling_place, "place_id"),
+                    (record.dangling_to_space, "to_space_id"),
+                ]
+                .into_iter()
+                .filter(|(dangling, _)| *dangling)
+                .map(|(_, field)| field.to_string())
+                .collect();
+
+                DanglingStockEntry {
+                    entry: StockEntry {
+                        id: record.id.into(),
+                        entry_timestamp: record.entry_timestamp,
+                        entry_type: record.entry_type,
+                        stock_quantity: record.stock_quantity,
+                        stock_item_id: record.stock_item_id,
+                        product_id: record.product_id,
+                        place_id: record.place_id,
+                        to_space_id: record.to_space_id,
+                        price: record.price,
+                        memo: record.memo,
+                        status: record.status,
+                    },
+                    dangling_fields,
+                }
+            })
+            .collect();
+
+        Ok(Json(entries))
+    }
+
+    /// Stock Entries: Confirm a pending entry
+    ///
+    /// Applies the entry's change to stock and marks it confirmed, in a single transaction.
+    #[oai(path = "/stock_entries/:id/confirm", method = "post")]
+    async fn confirm_stock_entry(
+        &self,
+        pool: Data<&PgPool>,
+        id: Path<i32>,
+    ) -> Result<ConfirmResponse> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let (entry, changes) = match confirm_pending_entry(&mut tx, id.0).await? {
+            Ok(confirmed) => confirmed,
+            Err(ConfirmError::NotFound(reason)) => {
+                return Ok(ConfirmResponse::NotFound(PlainText(reason)))
+            }
+            Err(ConfirmError::Conflict(reason)) => {
+                return Ok(ConfirmResponse::Conflict(PlainText(reason)))
+            }
+            Err(ConfirmError::Unprocessable(reason)) => {
+                return Ok(ConfirmResponse::Unprocessable(PlainText(reason)))
+            }
+        };
+        tx.commit().await.map_err(InternalServerError)?;
+        self.stock_totals.request();
+        self.publish_stock_changes(changes);
+
+        Ok(ConfirmResponse::Success(Json(entry)))
+    }
+
+    /// Stock Entries: Confirm several pending entries
+    ///
+    /// Applies the entries' changes to stock in order and marks them confirmed, in a single
+    /// transaction. If any of them can't be confirmed, none of them are.
+    #[oai(path = "/stock_entries/confirm_batch", method = "post")]
+    async fn confirm_stock_entries(
+        &self,
+        pool: Data<&PgPool>,
+        ids: Json<Vec<i32>>,
+    ) -> Result<ConfirmBatchResponse> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let mut entries = Vec::with_capacity(ids.len());
+        let mut changes = Vec::new();
+
+        for id in ids.0 {
+            match confirm_pending_entry(&mut tx, id).await? {
+                Ok((entry, entry_changes)) => {
+                    entries.push(entry);
+                    changes.extend(entry_changes);
+                }
+                Err(ConfirmError::NotFound(reason)) => {
+                    return Ok(ConfirmBatchResponse::NotFound(PlainText(reason)))
+                }
+                Err(ConfirmError::Conflict(reason)) => {
+                    return Ok(ConfirmBatchResponse::Conflict(PlainText(reason)))
+                }
+                Err(ConfirmError::Unprocessable(reason)) => {
+                    return Ok(ConfirmBatchResponse::Unprocessable(PlainText(reason)))
+                }
+            }
+        }
+        tx.commit().await.map_err(InternalServerError)?;
+        self.stock_totals.request();
+        self.publish_stock_changes(changes);
+
+        Ok(ConfirmBatchResponse::Success(Json(entries)))
+    }
+
+    /// Stock Entries: Transfer stock to another space
+    ///
+    /// Moves the quantity from the stock item to the product's stock item in the other space with
+    /// the same best by date (created if there is none), and records the `Transfer` entry, in a
+    /// single transaction. Returns the recorded entry.
+    #[oai(path = "/stock/transfer", method = "post")]
+    async fn transfer_stock(
+        &self,
+        pool: Data<&PgPool>,
+        transfer: Json<StockTransfer>,
+    ) -> Result<StockOperationResponse<StockEntry>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let item = sqlx::query!(
+            "SELECT product_id, space_id, stock_quantity FROM stock_items WHERE id = $1 FOR UPDATE",
+            transfer.stock_item_id
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+        let Some(item) = item else {
+            return Ok(StockOperationResponse::NotFound(PlainText(format!(
+                "No stock item with id '{}' found.",
+                transfer.stock_item_id
+            ))));
+        };
+        if item.space_id == transfer.to_space_id {
+            return Ok(StockOperationResponse::BadRequest(PlainText(format!(
+                "Stock item '{}' is already in space '{}'.",
+                transfer.stock_item_id, transfer.to_space_id
+            ))));
+        }
+        let space =
+            sqlx::query_scalar!("SELECT id FROM spaces WHERE id = $1", transfer.to_space_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(InternalServerError)?;
+        if space.is_none() {
+            return Ok(StockOperationResponse::NotFound(PlainText(format!(
+                "No space with id '{}' found.",
+                transfer.to_space_id
+            ))));
+        }
+        let quantity = transfer.quantity.unwrap_or(item.stock_quantity);
+        if quantity <= 0.0 {
+            return Ok(StockOperationResponse::Unprocessable(PlainText(format!(
+                "Stock item '{}' is out of stock.",
+                transfer.stock_item_id
+            ))));
+        }
+        if let Some(reason) = check_discrete_quantity(&mut *tx, item.product_id, quantity).await? {
+            return Ok(StockOperationResponse::Unprocessable(PlainText(reason)));
+        }
+
+        let transfer = transfer.0;
+        let draft = EntryDraft {
+            entry_type: EntryType::Transfer,
+            stock_quantity: quantity,
+            stock_item_id: transfer.stock_item_id,
+            product_id: Some(item.product_id),
+            place_id: None,
+            to_space_id: Some(transfer.to_space_id),
+            price: None,
+            memo: transfer.memo,
+        };
+        let (entry, changes) =
+            match record_stock_entry(&mut tx, draft, self.max_memo_length).await? {
+                Ok(recorded) => recorded,
+                Err(err) => return Ok(err.into()),
+            };
+        tx.commit().await.map_err(InternalServerError)?;
+        self.stock_totals.request();
+        self.publish_stock_changes(changes);
+
+        Ok(StockOperationResponse::Success(Json(entry)))
+    }
+
+    /// Stock Entries: Discard expired or spoiled stock
+    ///
+    /// Takes the quantity from the stock item and records the `Expire` entry, in a single
+    /// transaction, optionally deleting the stock item once it's empty. Returns the recorded
+    /// entry with the estimated value lost.
+    #[oai(path = "/stock/expire", method = "post")]
+    async fn expire_stock_item(
+        &self,
+        pool: Data<&PgPool>,
+        expiry: Json<StockExpiry>,
+    ) -> Result<StockOperationResponse<ExpiredStock>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let item = sqlx::query!(
+            r#"
+SELECT si.product_id, si.stock_quantity, lp.price::numeric::float8 AS unit_price
+FROM stock_items si
+LEFT JOIN latest_purchase_prices lp ON lp.product_id = si.product_id
+WHERE si.id = $1
+FOR UPDATE OF si"#,
+            expiry.stock_item_id
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+        let Some(item) = item else {
+            return Ok(StockOperationResponse::NotFound(PlainText(format!(
+                "No stock item with id '{}' found.",
+                expiry.stock_item_id
+            ))));
+        };
+        let quantity = expiry.quantity.unwrap_or(item.stock_quantity);
+        if quantity <= 0.0 {
+            return Ok(StockOperationResponse::Unprocessable(PlainText(format!(
+                "Stock item '{}' is out of stock.",
+                expiry.stock_item_id
+            ))));
+        }
+        if let Some(reason) = check_discrete_quantity(&mut *tx, item.product_id, quantity).await? {
+            return Ok(StockOperationResponse::Unprocessable(PlainText(reason)));
+        }
+
+        let expiry = expiry.0;
+        let draft = EntryDraft {
+            entry_type: EntryType::Expire,
+            stock_quantity: quantity,
+            stock_item_id: expiry.stock_item_id,
+            product_id: Some(item.product_id),
+            place_id: None,
+            to_space_id: None,
+            price: None,
+            memo: expiry.memo,
+        };
+        let (entry, mut changes) =
+            match record_stock_entry(&mut tx, draft, self.max_memo_length).await? {
+                Ok(recorded) => recorded,
+                Err(err) => return Ok(err.into()),
+            };
+        let deleted = if expiry.delete_if_empty.unwrap_or(false) {
+            sqlx::query!(
+                r#"
+WITH deleted AS (
+    DELETE FROM stock_items
+    WHERE id = $1 AND stock_quantity <= $2::float8
+    RETURNING id, product_id, space_id, best_by_date
+)
+INSERT INTO deleted_stock_items (id, product_id, space_id, best_by_date)
+SELECT id, product_id, space_id, best_by_date FROM deleted
+RETURNING id"#,
+                expiry.stock_item_id,
+                QUANTITY_TOLERANCE * f64::from(quantity)
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(InternalServerError)?
+            .is_some()
+        } else {
+            false
+        };
+        if deleted {
+            for change in &mut changes {
+                if change.stock_item_id == expiry.stock_item_id {
+                    change.stock_quantity = 0.0;
+                }
+            }
+        }
+        tx.commit().await.map_err(InternalServerError)?;
+        self.stock_totals.request();
+        self.publish_stock_changes(changes);
+
+        Ok(StockOperationResponse::Success(Json(ExpiredStock {
+            entry,
+            estimated_value: item.unit_price.map(|price| price * f64::from(quantity)),
+            deleted,
+        })))
+    }
+
+    /// Stock Entries: Transfer all stock of several products between spaces
+    ///
+    /// Records and applies a `Transfer` entry for every stock item of the products in the source
+    /// space, in a single transaction. Products without stock in the source space are skipped.
+    /// Returns the recorded entries.
+    #[oai(path = "/stock/transfer_bulk", method = "post")]
+    async fn transfer_bulk(
+        &self,
+        pool: Data<&PgPool>,
+        transfer: Json<BulkTransfer>,
+    ) -> Result<StockOperationResponse<Vec<StockEntry>>> {
+        if transfer.from_space_id == transfer.to_space_id {
+            return Ok(StockOperationResponse::BadRequest(PlainText(format!(
+                "Can't transfer from space '{}' to itself.",
+                transfer.from_space_id
+            ))));
+        }
+        for space_id in [transfer.from_space_id, transfer.to_space_id] {
+            let space = sqlx::query_scalar!("SELECT id FROM spaces WHERE id = $1", space_id)
+                .fetch_optional(pool.0)
+                .await
+                .map_err(InternalServerError)?;
+            if space.is_none() {
+                return Ok(StockOperationResponse::NotFound(PlainText(format!(
+                    "No space with id '{space_id}' found."
+                ))));
+            }
+        }
+
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let items = sqlx::query!(
+            r#"
+SELECT id, product_id, stock_quantity
+FROM stock_items
+WHERE space_id = $1 AND product_id = ANY($2) AND stock_quantity > 0
+ORDER BY product_id, best_by_date NULLS LAST, id
+FOR UPDATE"#,
+            transfer.from_space_id,
+            &transfer.product_ids
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut entries = Vec::with_capacity(items.len());
+        let mut changes = Vec::new();
+        for item in items {
+            let draft = EntryDraft {
+                entry_type: EntryType::Transfer,
+                stock_quantity: item.stock_quantity,
+                stock_item_id: item.id,
+                product_id: Some(item.product_id),
+                place_id: None,
+                to_space_id: Some(transfer.to_space_id),
+                price: None,
+                memo: None,
+            };
+            match record_stock_entry(&mut tx, draft, self.max_memo_length).await? {
+                Ok((entry, entry_changes)) => {
+                    entries.push(entry);
+                    changes.extend(entry_changes);
+                }
+                Err(err) => return Ok(err.into()),
+            }
+        }
+        tx.commit().await.map_err(InternalServerError)?;
+        if !entries.is_empty() {
+            self.stock_totals.request();
+            self.publish_stock_changes(changes);
+        }
+
+        Ok(StockOperationResponse::Success(Json(entries)))
+    }
+
+    /// Stock Entries: Record a purchase
+    ///
+    /// Adds the purchased quantity, converted to the product's stock unit with its purchase to
+    /// stock factor, to the product's stock item in the space with the same best by date (created
+    /// if there is none), and records the `Purchase` entry, in a single transaction. Products
+    /// without a purchase to stock factor are purchased in their stock unit. Returns the recorded
+    /// entry, with the price per stock unit.
+    #[oai(path = "/stock/purchase", method = "post")]
+    async fn purchase_stock(
+        &self,
+        pool: Data<&PgPool>,
+        purchase: Json<StockPurchase>,
+    ) -> Result<StockOperationResponse<StockEntry>> {
+        let factor = sqlx::query_scalar!(
+            "SELECT purchase_to_stock_factor FROM products WHERE id = $1",
+            purchase.product_id
+        )
+        .fetch_optional(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+        let Some(factor) = factor else {
+            return Ok(StockOperationResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                purchase.product_id
+            ))));
+        };
+        let factor = factor.filter(|&factor| factor > 0.0).unwrap_or(1.0);
+        let space = sqlx::query_scalar!("SELECT id FROM spaces WHERE id = $1", purchase.space_id)
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?;
+        if space.is_none() {
+            return Ok(StockOperationResponse::NotFound(PlainText(format!(
+                "No space with id '{}' found.",
+                purchase.space_id
+            ))));
+        }
+        if let Some(place_id) = purchase.place_id {
+            let place = sqlx::query_scalar!("SELECT id FROM places WHERE id = $1", place_id)
+                .fetch_optional(pool.0)
+                .await
+                .map_err(InternalServerError)?;
+            if place.is_none() {
+                return Ok(StockOperationResponse::NotFound(PlainText(format!(
+                    "No place with id '{place_id}' found."
+                ))));
+            }
+        }
+        let stock_quantity = purchase.quantity * factor;
+        if let Some(reason) =
+            check_discrete_quantity(pool.0, purchase.product_id, stock_quantity).await?
+        {
+            return Ok(StockOperationResponse::Unprocessable(PlainText(reason)));
+        }
+
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let stock_item_id = sqlx::query_scalar!(
+            r#"
+INSERT INTO stock_items (product_id, space_id, stock_quantity, best_by_date)
+VALUES ($1, $2, 0, $3)
+ON CONFLICT (product_id, space_id, best_by_date)
+DO UPDATE SET stock_quantity = stock_items.stock_quantity
+RETURNING id"#,
+            purchase.product_id,
+            purchase.space_id,
+            purchase.best_by_date
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let purchase = purchase.0;
+        let draft = EntryDraft {
+            entry_type: EntryType::Purchase,
+            stock_quantity,
+            stock_item_id,
+            product_id: Some(purchase.product_id),
+            place_id: purchase.place_id,
+            to_space_id: None,
+            price: purchase.price.map(|price| price / factor),
+            memo: purchase.memo,
+        };
+        let (entry, changes) =
+            match record_stock_entry(&mut tx, draft, self.max_memo_length).await? {
+                Ok(recorded) => recorded,
+                Err(err) => return Ok(err.into()),
+            };
+        tx.commit().await.map_err(InternalServerError)?;
+        self.stock_totals.request();
+        self.publish_stock_changes(changes);
+
+        Ok(StockOperationResponse::Success(Json(entry)))
+    }
+
+    /// Stock Entries: Record a consumption
+    ///
+    /// Takes the consumed quantity from the product's stock items in the order they are used up:
+    /// soonest best by date first (and undated stock last) for `fefo` products, or first received
+    /// first for `fifo` products. Records a `Consume` entry per stock item taken from, in a single
+    /// transaction. Consuming more than the product's total stock is rejected. Returns the
+    /// recorded entries.
+    #[oai(path = "/stock/consume", method = "post")]
+    async fn consume_stock(
+        &self,
+        pool: Data<&PgPool>,
+        consumption: Json<StockConsumption>,
+    ) -> Result<StockOperationResponse<Vec<StockEntry>>> {
+        let product = sqlx::query_scalar!(
+            "SELECT id FROM products WHERE id = $1",
+            consumption.product_id
         )
         .fetch_optional(pool.0)
         .await
         .map_err(InternalServerError)?;
+        if product.is_none() {
+            return Ok(StockOperationResponse::NotFound(PlainText(format!(
+                "No product with id '{}' found.",
+                consumption.product_id
+            ))));
+        }
+        if let Some(reason) =
+            check_discrete_quantity(pool.0, consumption.product_id, consumption.quantity).await?
+        {
+            return Ok(StockOperationResponse::Unprocessable(PlainText(reason)));
+        }
+
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let items = sqlx::query!(
+            r#"
+SELECT si.id, si.stock_quantity
+FROM stock_items si
+JOIN products p ON p.id = si.product_id
+WHERE si.product_id = $1 AND si.stock_quantity > 0
+ORDER BY CASE WHEN p.depletion_strategy = 'fefo' THEN si.best_by_date END NULLS LAST,
+    si.created_at, si.id
+FOR UPDATE OF si"#,
+            consumption.product_id
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let in_stock: f64 = items
+            .iter()
+            .map(|item| f64::from(item.stock_quantity))
+            .sum();
+        let wanted = f64::from(consumption.quantity);
+        if in_stock < wanted * (1.0 - QUANTITY_TOLERANCE) {
+            return Ok(StockOperationResponse::Unprocessable(PlainText(format!(
+                "Insufficient stock of product '{}' (short by {}).",
+                consumption.product_id,
+                wanted - in_stock
+            ))));
+        }
+
+        let mut remaining = consumption.quantity;
+        let mut entries = Vec::new();
+        let mut changes = Vec::new();
+        for item in items {
+            if remaining <= 0.0 {
+                break;
+            }
+            let taken = remaining.min(item.stock_quantity);
+            remaining -= taken;
+
+            let draft = EntryDraft {
+                entry_type: EntryType::Consume,
+                stock_quantity: taken,
+                stock_item_id: item.id,
+                product_id: Some(consumption.product_id),
+                place_id: None,
+                to_space_id: None,
+                price: None,
+                memo: consumption.memo.clone(),
+            };
+            match record_stock_entry(&mut tx, draft, self.max_memo_length).await? {
+                Ok((entry, entry_changes)) => {
+                    entries.push(entry);
+                    changes.extend(entry_changes);
+                }
+                Err(err) => return Ok(err.into()),
+            }
+        }
+        tx.commit().await.map_err(InternalServerError)?;
+        self.stock_totals.request();
+        self.publish_stock_changes(changes);
+
+        Ok(StockOperationResponse::Success(Json(entries)))
+    }
+
+    // EVENTS
+    /// Events: Stream stock changes
+    ///
+    /// Sends the new quantity of every changed stock item as a server-sent event, as they
+    /// happen. Deleted stock items are sent with a quantity of 0.
+    #[oai(path = "/events/stock", method = "get")]
+    async fn stock_events(&self) -> EventStream<BoxStream<'static, StockChange>> {
+        let receiver = self.stock_events.subscribe();
+        let changes = stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(change) => return Some((change, receiver)),
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "stock event subscriber fell behind");
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        EventStream::new(changes.boxed()).keep_alive(Duration::from_secs(15))
+    }
+
+    // IMPORT
+    /// Import: Fetch the order to insert into tables in
+    ///
+    /// Sorts the tables so that every table comes after the tables its foreign keys reference,
+    /// read from the database schema. References of a table to itself are left out.
+    #[oai(path = "/import/order", method = "get")]
+    async fn get_import_order(&self, pool: Data<&PgPool>) -> Result<GetAllResponse<ImportStep>> {
+        let tables = sqlx::query_scalar!(
+            r#"
+SELECT tablename AS "tablename!"
+FROM pg_tables
+WHERE schemaname = current_schema() AND tablename NOT LIKE '\_sqlx%'
+ORDER BY tablename"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+        let references = sqlx::query!(
+            r#"
+SELECT DISTINCT src.relname AS "table!", dst.relname AS "references!"
+FROM pg_constraint c
+JOIN pg_class src ON src.oid = c.conrelid
+JOIN pg_class dst ON dst.oid = c.confrelid
+WHERE c.contype = 'f' AND c.conrelid <> c.confrelid
+    AND src.relnamespace = current_schema()::regnamespace"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut depends_on: HashMap<String, Vec<String>> = HashMap::new();
+        for reference in references {
+            depends_on
+                .entry(reference.table)
+                .or_default()
+                .push(reference.references);
+        }
+
+        // Repeatedly take the tables whose dependencies have all been taken, alphabetically
+        let mut remaining = tables;
+        let mut taken = HashSet::new();
+        let mut order = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            let (mut ready, blocked): (Vec<String>, Vec<String>) =
+                remaining.into_iter().partition(|table| {
+                    depends_on
+                        .get(table)
+                        .into_iter()
+                        .flatten()
+                        .all(|dependency| taken.contains(dependency))
+                });
+            if ready.is_empty() {
+                // Tables in a cycle of references can never be ready, so add them as they are
+                ready = blocked;
+                remaining = Vec::new();
+            } else {
+                remaining = blocked;
+            }
+
+            for table in ready {
+                let mut dependencies = depends_on.remove(&table).unwrap_or_default();
+                dependencies.sort();
+                taken.insert(table.clone());
+                order.push(ImportStep {
+                    table,
+                    depends_on: dependencies,
+                });
+            }
+        }
+
+        Ok(Json(order))
+    }
+
+    // BATCH
+    /// Batch: Run several creations in a single transaction
+    ///
+    /// The operations run in order, and later operations can reference the ids created by earlier
+    /// ones as `"$<index>.id"`. If any of them fails, none of them are applied.
+    #[oai(path = "/batch", method = "post")]
+    async fn batch(
+        &self,
+        pool: Data<&PgPool>,
+        operations: Json<Vec<BatchOperation>>,
+    ) -> Result<BatchResponse> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let mut ids = Vec::with_capacity(operations.len());
+        let mut totals_changed = false;
+        let mut changes = Vec::new();
+
+        for (index, mut operation) in operations.0.into_iter().enumerate() {
+            let path = operation.path.clone();
+            if let Err(reason) = resolve_references(&mut operation.body, &ids) {
+                return Ok(BatchResponse::Unprocessable(PlainText(format!(
+                    "Operation {index} ({path}) failed: {reason}"
+                ))));
+            }
+
+            match run_batch_operation(&mut tx, operation).await? {
+                Ok((id, change)) => {
+                    ids.push(id);
+                    changes.extend(change);
+                }
+                Err(OperationError::Conflict(reason)) => {
+                    return Ok(BatchResponse::Conflict(PlainText(format!(
+                        "Operation {index} ({path}) failed: {reason}"
+                    ))))
+                }
+                Err(OperationError::Unprocessable(reason)) => {
+                    return Ok(BatchResponse::Unprocessable(PlainText(format!(
+                        "Operation {index} ({path}) failed: {reason}"
+                    ))))
+                }
+            }
+            totals_changed |= path == "/stock_item" || path == "/products";
+        }
+        tx.commit().await.map_err(InternalServerError)?;
+        if totals_changed {
+            self.stock_totals.request();
+        }
+        self.publish_stock_changes(changes);
+
+        Ok(BatchResponse::Success(Json(
+            ids.into_iter().map(|id| BatchResult { id }).collect(),
+        )))
+    }
+
+    // MAINTENANCE
+    /// Maintenance: Refresh the cached product stock totals
+    #[oai(path = "/maintenance/refresh_stock_totals", method = "post")]
+    async fn refresh_stock_totals_cache(&self, pool: Data<&PgPool>) -> Result<Json<StockTotals>> {
+        refresh_stock_totals(pool.0)
+            .await
+            .map_err(InternalServerError)?;
+
+        self.get_stock_totals_report(pool).await
+    }
+
+    /// Maintenance: Clamp negative stock to zero
+    ///
+    /// Zeroes every stock item with a negative quantity, recording an `Adjust` entry for each
+    /// correction. Returns the recorded entries.
+    #[oai(path = "/maintenance/clamp_negative_stock", method = "post")]
+    async fn clamp_negative_stock(
+        &self,
+        pool: Data<&PgPool>,
+    ) -> Result<GetAllResponse<StockEntry>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let entries = sqlx::query_as!(
+            StockEntry,
+            r#"
+WITH clamped AS (
+    UPDATE stock_items si
+    SET stock_quantity = 0
+    FROM (SELECT id, stock_quantity FROM stock_items WHERE stock_quantity < 0 FOR UPDATE) n
+    WHERE si.id = n.id
+    RETURNING si.id, si.product_id, n.stock_quantity
+)
+INSERT INTO stock_entries (entry_type, stock_quantity, stock_item_id, product_id, memo)
+SELECT 'adjust', -stock_quantity, id, product_id,
+    'Clamped negative stock of ' || stock_quantity || ' to zero'
+FROM clamped
+RETURNING id, entry_timestamp, entry_type AS "entry_type: EntryType", stock_quantity,
+    stock_item_id, product_id, place_id, to_space_id, price, memo,
+    status AS "status: EntryStatus""#
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+        let item_ids: Vec<i32> = entries
+            .iter()
+            .filter_map(|entry| entry.stock_item_id)
+            .collect();
+        let changes = sqlx::query_as!(
+            StockChange,
+            r#"
+SELECT id AS stock_item_id, product_id, space_id, stock_quantity
+FROM stock_items
+WHERE id = ANY($1)"#,
+            &item_ids
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+        tx.commit().await.map_err(InternalServerError)?;
+        if !entries.is_empty() {
+            self.stock_totals.request();
+            self.publish_stock_changes(changes);
+        }
+
+        Ok(Json(entries))
+    }
+
+    /// Maintenance: Rebuild stock from the stock entries
+    ///
+    /// Recomputes the quantity of every stock item by replaying all confirmed stock entries in
+    /// order, treating the entries as the source of truth. Transfers into a lot without a stock
+    /// item create one, including transfers from deleted stock items. A transfer whose source
+    /// stock item is unknown leaves its destination lot unknown too, so the rebuild is rejected
+    /// unless it's a dry run. Only available when `ALLOW_STOCK_REBUILD` is set.
+    #[oai(path = "/maintenance/rebuild_stock", method = "post")]
+    async fn rebuild_stock(
+        &self,
+        pool: Data<&PgPool>,
+        /// Only compute the rebuilt quantities, without storing them
+        dry_run: Query<Option<bool>>,
+    ) -> Result<RebuildResponse> {
+        if !self.allow_stock_rebuild {
+            return Ok(RebuildResponse::Forbidden(PlainText(
+                "Rebuilding stock is disabled, set `ALLOW_STOCK_REBUILD` to enable it.".to_string(),
+            )));
+        }
+        let dry_run = dry_run.0.unwrap_or(false);
+
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let items = sqlx::query!(
+            r#"
+SELECT id, product_id, space_id, best_by_date, stock_quantity
+FROM stock_items
+ORDER BY id
+FOR UPDATE"#
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+        let entries = sqlx::query!(
+            r#"
+SELECT id, entry_type AS "entry_type: EntryType", stock_quantity, stock_item_id, to_space_id
+FROM stock_entries
+WHERE status = 'confirmed'
+ORDER BY entry_timestamp, id"#
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut lots: HashMap<(i32, i32, Option<NaiveDate>), i32> = items
+            .iter()
+            .map(|item| ((item.product_id, item.space_id, item.best_by_date), item.id))
+            .collect();
+        let deleted_items =
+            sqlx::query!("SELECT id, product_id, space_id, best_by_date FROM deleted_stock_items")
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(InternalServerError)?;
+        let mut details: HashMap<i32, (i32, i32, Option<NaiveDate>)> = items
+            .iter()
+            .map(|item| (item.id, (item.product_id, item.space_id, item.best_by_date)))
+            .chain(
+                deleted_items
+                    .iter()
+                    .map(|item| (item.id, (item.product_id, item.space_id, item.best_by_date))),
+            )
+            .collect();
+        let mut quantities: HashMap<i32, f32> = items.iter().map(|item| (item.id, 0.0)).collect();
+        let mut rebuilt: Vec<RebuiltStockItem> = items
+            .iter()
+            .map(|item| RebuiltStockItem {
+                id: item.id,
+                before: item.stock_quantity,
+                after: 0.0,
+            })
+            .collect();
+        let mut skipped_entry_ids = Vec::new();
+        let mut unknown_transfer_ids = Vec::new();
+
+        for entry in entries {
+            let Some(stock_item_id) = entry.stock_item_id.filter(|id| details.contains_key(id))
+            else {
+                if matches!(entry.entry_type, EntryType::Transfer) {
+                    unknown_transfer_ids.push(entry.id);
+                } else {
+                    skipped_entry_ids.push(entry.id);
+                }
+                continue;
+            };
+            let delta = match entry.entry_type {
+                EntryType::Purchase | EntryType::Adjust => entry.stock_quantity,
+                EntryType::Transfer | EntryType::Consume | EntryType::Expire => {
+                    -entry.stock_quantity
+                }
+            };
+            match quantities.get_mut(&stock_item_id) {
+                Some(quantity) => *quantity += delta,
+                None if !matches!(entry.entry_type, EntryType::Transfer) => {
+                    skipped_entry_ids.push(entry.id);
+                }
+                None => {}
+            }
+
+            if let (EntryType::Transfer, Some(to_space_id)) = (entry.entry_type, entry.to_space_id)
+            {
+                let (product_id, _, best_by_date) = details[&stock_item_id];
+                let to_item_id = match lots.get(&(product_id, to_space_id, best_by_date)) {
+                    Some(&id) => id,
+                    None => {
+                        let id = sqlx::query_scalar!(
+                            r#"
+INSERT INTO stock_items (product_id, space_id, stock_quantity, best_by_date)
+VALUES ($1, $2, 0, $3)
+RETURNING id"#,
+                            product_id,
+                            to_space_id,
+                            best_by_date
+                        )
+                        .fetch_one(&mut *tx)
+                        .await
+                        .map_err(InternalServerError)?;
+                        lots.insert((product_id, to_space_id, best_by_date), id);
+                        details.insert(id, (product_id, to_space_id, best_by_date));
+                        rebuilt.push(RebuiltStockItem {
+                            id,
+                            before: 0.0,
+                            after: 0.0,
+                        });
+                        id
+                    }
+                };
+                *quantities.entry(to_item_id).or_default() += entry.stock_quantity;
+            }
+        }
+
+        if !unknown_transfer_ids.is_empty() && !dry_run {
+            return Ok(RebuildResponse::Conflict(PlainText(format!(
+                "The transfers {unknown_transfer_ids:?} can't be replayed, as the stock item they \
+                moved stock from is unknown."
+            ))));
+        }
+
+        let mut changes = Vec::new();
+        for item in &mut rebuilt {
+            item.after = quantities[&item.id];
+            if item.after != item.before {
+                sqlx::query!(
+                    "UPDATE stock_items SET stock_quantity = $2 WHERE id = $1",
+                    item.id,
+                    item.after
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(InternalServerError)?;
+                let (product_id, space_id, _) = details[&item.id];
+                changes.push(StockChange {
+                    stock_item_id: item.id,
+                    product_id,
+                    space_id,
+                    stock_quantity: item.after,
+                });
+            }
+        }
+
+        if dry_run {
+            tx.rollback().await.map_err(InternalServerError)?;
+        } else {
+            tx.commit().await.map_err(InternalServerError)?;
+            self.stock_totals.request();
+            self.publish_stock_changes(changes);
+        }
+
+        Ok(RebuildResponse::Success(Json(StockRebuild {
+            dry_run,
+            items: rebuilt,
+            skipped_entry_ids,
+            unknown_transfer_ids,
+        })))
+    }
+
+    /// Maintenance: Permanently remove deleted rows
+    ///
+    /// Removes the unit conversions deleted more than the given number of days ago, which can
+    /// then no longer be restored. Returns the number of rows removed per table.
+    #[oai(path = "/maintenance/purge_deleted", method = "post")]
+    async fn purge_deleted(
+        &self,
+        pool: Data<&PgPool>,
+        /// The number of days deleted rows are kept for
+        #[oai(validator(minimum(value = "0")))]
+        older_than_days: Query<i32>,
+    ) -> Result<Json<PurgedRows>> {
+        let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+        let unit_conversions = sqlx::query!(
+            r#"
+DELETE FROM unit_conversions
+WHERE deleted_at < LOCALTIMESTAMP - make_interval(days => $1)"#,
+            older_than_days.0
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(InternalServerError)?
+        .rows_affected();
+        tx.commit().await.map_err(InternalServerError)?;
+
+        Ok(Json(PurgedRows { unit_conversions }))
+    }
+
+    /// Maintenance: Check for dangling references
+    ///
+    /// Looks for rows referencing rows of another table that don't exist, such as stock entries
+    /// of deleted stock items or rows left behind by manual edits and imports.
+    #[oai(path = "/maintenance/integrity_check", method = "get")]
+    async fn check_integrity(&self, pool: Data<&PgPool>) -> Result<Json<IntegrityReport>> {
+        let (
+            item_products,
+            item_spaces,
+            entry_items,
+            entry_products,
+            entry_places,
+            entry_spaces,
+            parent_products,
+            purchase_units,
+            stock_units,
+            from_units,
+            to_units,
+        ) = tokio::try_join!(
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM stock_items x
+WHERE x.product_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM products r WHERE r.id = x.product_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM stock_items x
+WHERE x.space_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM spaces r WHERE r.id = x.space_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM stock_entries x
+WHERE x.stock_item_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM stock_items r WHERE r.id = x.stock_item_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM stock_entries x
+WHERE x.product_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM products r WHERE r.id = x.product_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM stock_entries x
+WHERE x.place_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM places r WHERE r.id = x.place_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM stock_entries x
+WHERE x.to_space_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM spaces r WHERE r.id = x.to_space_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM products x
+WHERE x.parent_product_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM products r WHERE r.id = x.parent_product_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM products x
+WHERE x.purchase_unit_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM units r WHERE r.id = x.purchase_unit_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM products x
+WHERE x.stock_unit_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM units r WHERE r.id = x.stock_unit_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM unit_conversions x
+WHERE x.from_unit_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM units r WHERE r.id = x.from_unit_id)"#
+            )
+            .fetch_one(pool.0),
+            sqlx::query_as!(
+                DanglingIds,
+                r#"
+SELECT COUNT(*) AS "count!",
+    COALESCE((array_agg(x.id ORDER BY x.id))[1:10], '{}') AS "sample_ids!"
+FROM unit_conversions x
+WHERE x.to_unit_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM units r WHERE r.id = x.to_unit_id)"#
+            )
+            .fetch_one(pool.0),
+        )
+        .map_err(InternalServerError)?;
+
+        let references: Vec<DanglingReference> = [
+            ("stock_items", "product_id", "products", item_products),
+            ("stock_items", "space_id", "spaces", item_spaces),
+            ("stock_entries", "stock_item_id", "stock_items", entry_items),
+            ("stock_entries", "product_id", "products", entry_products),
+            ("stock_entries", "place_id", "places", entry_places),
+            ("stock_entries", "to_space_id", "spaces", entry_spaces),
+            ("products", "parent_product_id", "products", parent_products),
+            ("products", "purchase_unit_id", "units", purchase_units),
+            ("products", "stock_unit_id", "units", stock_units),
+            ("unit_conversions", "from_unit_id", "units", from_units),
+            ("unit_conversions", "to_unit_id", "units", to_units),
+        ]
+        .into_iter()
+        .map(|(table, column, referenced_table, ids)| DanglingReference {
+            table: table.to_string(),
+            column: column.to_string(),
+            referenced_table: referenced_table.to_string(),
+            count: ids.count,
+            sample_ids: ids.sample_ids,
+        })
+        .collect();
+        let total_count = references.iter().map(|reference| reference.count).sum();
+
+        Ok(Json(IntegrityReport {
+            references,
+            total_count,
+        }))
+    }
+
+    // SNAPSHOTS
+    /// Snapshots: Record the current stock
+    ///
+    /// Records the total stock of every product, returning the recorded rows. Snapshots are also
+    /// taken every `SNAPSHOT_INTERVAL` seconds when it's set.
+    #[oai(path = "/snapshots", method = "post")]
+    async fn take_snapshot(
+        &self,
+        pool: Data<&PgPool>,
+        /// Whether to also record the total stock of every product per space
+        per_space: Query<Option<bool>>,
+    ) -> Result<GetAllResponse<InventorySnapshot>> {
+        let snapshot = take_inventory_snapshot(pool.0, per_space.0.unwrap_or(false))
+            .await
+            .map_err(InternalServerError)?;
+
+        Ok(Json(snapshot))
+    }
+
+    /// Snapshots: Fetch all within a period
+    #[oai(path = "/snapshots", method = "get")]
+    async fn get_snapshots(
+        &self,
+        pool: Data<&PgPool>,
+        from: Query<Option<NaiveDate>>,
+        to: Query<Option<NaiveDate>>,
+        /// Only fetch the snapshots of this product
+        product_id: Query<Option<i32>>,
+    ) -> Result<ReportResponse<Vec<InventorySnapshot>>> {
+        if let (Some(from), Some(to)) = (from.0, to.0) {
+            if from > to {
+                return Ok(ReportResponse::BadRequest(PlainText(format!(
+                    "The start of the period '{from}' is after its end '{to}'."
+                ))));
+            }
+        }
+
+        let snapshots = sqlx::query_as!(
+            InventorySnapshot,
+            r#"
+SELECT id, taken_at, product_id, space_id, total_quantity
+FROM inventory_snapshots
+WHERE ($1::date IS NULL OR taken_at >= $1::date)
+  AND ($2::date IS NULL OR taken_at < $2::date + 1)
+  AND ($3::integer IS NULL OR product_id = $3)
+ORDER BY taken_at, product_id, space_id NULLS FIRST"#,
+            from.0,
+            to.0,
+            product_id.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(ReportResponse::Success(Json(snapshots)))
+    }
+
+    // REPORTS
+    /// Reports: Total stock per product
+    ///
+    /// Served from a cache that is refreshed in the background shortly after stock or the products
+    /// change, or on demand through
+    /// `/maintenance/refresh_stock_totals`. Totals are also given in the largest unit they are at
+    /// least 1 of (such as 1.5 kg rather than 1500 g), going by the unit conversions.
+    #[oai(path = "/reports/stock_totals", method = "get")]
+    async fn get_stock_totals_report(&self, pool: Data<&PgPool>) -> Result<Json<StockTotals>> {
+        let graph = ConversionGraph::load(pool.0, self.max_conversion_path_length)
+            .await
+            .map_err(InternalServerError)?;
+        let records = sqlx::query!(
+            r#"
+SELECT t.product_id AS "product_id!", p.name, t.total_quantity AS "total_quantity!",
+    p.stock_unit_id, u.decimal_places AS "decimal_places?",
+    t.stock_item_count AS "stock_item_count!"
+FROM product_stock_totals t
+JOIN products p ON p.id = t.product_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+ORDER BY t.product_id"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+        let stale_as_of = sqlx::query_scalar!("SELECT refreshed_at FROM stock_totals_refresh")
+            .fetch_optional(pool.0)
+            .await
+            .map_err(InternalServerError)?;
+
+        Ok(Json(StockTotals {
+            stale_as_of,
+            totals: records
+                .into_iter()
+                .map(|record| {
+                    let total_quantity =
+                        round_to_unit(record.total_quantity, record.decimal_places);
+                    let (display_unit_id, display_quantity) = match record.stock_unit_id {
+                        Some(unit) => {
+                            let (unit, quantity) = graph.display(unit, total_quantity);
+                            (Some(unit), quantity)
+                        }
+                        None => (None, total_quantity),
+                    };
+
+                    ProductStockTotal {
+                        product_id: record.product_id,
+                        name: record.name,
+                        total_quantity,
+                        stock_unit_id: record.stock_unit_id,
+                        display_quantity,
+                        display_unit_id,
+                        stock_item_count: record.stock_item_count,
+                    }
+                })
+                .collect(),
+        }))
+    }
+
+    /// Reports: Waste from expired stock
+    ///
+    /// Sums the `Expire` stock entries within the (inclusive) date window, grouped by product or space.
+    /// Values are estimated from the latest purchase price of each product. The entries that can't
+    /// be assigned to a product or space are grouped without an id. Quantities are rounded per
+    /// product, to the decimal places of its unit.
+    #[oai(path = "/reports/waste", method = "get")]
+    async fn get_waste_report(
+        &self,
+        pool: Data<&PgPool>,
+        from: Query<Option<NaiveDate>>,
+        to: Query<Option<NaiveDate>>,
+        group_by: Query<Option<WasteGrouping>>,
+    ) -> Result<Json<WasteReport>> {
+        let groups = match group_by.0.unwrap_or(WasteGrouping::Product) {
+            WasteGrouping::Product => sqlx::query_as!(
+                WasteGroup,
+                r#"
+WITH waste AS (
+    SELECT COALESCE(e.product_id, si.product_id, d.product_id) AS product_id, e.stock_quantity
+    FROM stock_entries e
+    LEFT JOIN stock_items si ON si.id = e.stock_item_id
+    LEFT JOIN deleted_stock_items d ON d.id = e.stock_item_id
+    WHERE e.entry_type = 'expire' AND e.status = 'confirmed'
+      AND ($1::date IS NULL OR e.entry_timestamp >= $1::date)
+      AND ($2::date IS NULL OR e.entry_timestamp < $2::date + 1)
+)
+SELECT p.id AS "id?", p.name AS "name?",
+    COUNT(*) AS "entry_count!",
+    round_quantity(SUM(w.stock_quantity)::float8, u.decimal_places) AS "total_quantity!",
+    SUM(w.stock_quantity::numeric * lp.price::numeric)::float8 AS estimated_value
+FROM waste w
+LEFT JOIN products p ON p.id = w.product_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+LEFT JOIN latest_purchase_prices lp ON lp.product_id = w.product_id
+GROUP BY p.id, u.decimal_places
+ORDER BY 4 DESC"#,
+                from.0,
+                to.0
+            )
+            .fetch_all(pool.0)
+            .await
+            .map_err(InternalServerError)?,
+            WasteGrouping::Space => sqlx::query_as!(
+                WasteGroup,
+                r#"
+WITH waste AS (
+    SELECT COALESCE(e.product_id, si.product_id, d.product_id) AS product_id,
+        COALESCE(si.space_id, d.space_id) AS space_id, e.stock_quantity
+    FROM stock_entries e
+    LEFT JOIN stock_items si ON si.id = e.stock_item_id
+    LEFT JOIN deleted_stock_items d ON d.id = e.stock_item_id
+    WHERE e.entry_type = 'expire' AND e.status = 'confirmed'
+      AND ($1::date IS NULL OR e.entry_timestamp >= $1::date)
+      AND ($2::date IS NULL OR e.entry_timestamp < $2::date + 1)
+), product_waste AS (
+    SELECT w.space_id, COUNT(*) AS entry_count,
+        round_quantity(SUM(w.stock_quantity)::float8, u.decimal_places) AS total_quantity,
+        SUM(w.stock_quantity::numeric * lp.price::numeric) AS estimated_value
+    FROM waste w
+    LEFT JOIN products p ON p.id = w.product_id
+    LEFT JOIN units u ON u.id = p.stock_unit_id
+    LEFT JOIN latest_purchase_prices lp ON lp.product_id = w.product_id
+    GROUP BY w.space_id, w.product_id, u.decimal_places
+)
+SELECT s.id AS "id?", s.name AS "name?",
+    SUM(pw.entry_count)::int8 AS "entry_count!",
+    SUM(pw.total_quantity) AS "total_quantity!",
+    SUM(pw.estimated_value)::float8 AS estimated_value
+FROM product_waste pw
+LEFT JOIN spaces s ON s.id = pw.space_id
+GROUP BY s.id
+ORDER BY 4 DESC"#,
+                from.0,
+                to.0
+            )
+            .fetch_all(pool.0)
+            .await
+            .map_err(InternalServerError)?,
+        };
+
+        Ok(Json(WasteReport {
+            entry_count: groups.iter().map(|group| group.entry_count).sum(),
+            total_quantity: groups.iter().map(|group| group.total_quantity).sum(),
+            estimated_value: groups
+                .iter()
+                .filter_map(|group| group.estimated_value)
+                .sum(),
+            groups,
+        }))
+    }
+
+    /// Reports: Projected waste from expiring stock
+    ///
+    /// Projects how much of the stock expiring within the window will still be in stock when it
+    /// expires, if each product keeps being consumed at its rate over the last 30 days, soonest
+    /// expiring first. Stock that has already expired is wasted entirely.
+    #[oai(path = "/reports/projected_waste", method = "get")]
+    async fn get_projected_waste_report(
+        &self,
+        pool: Data<&PgPool>,
+        /// The number of days ahead to project for (defaults to 30)
+        #[oai(validator(minimum(value = "0"), maximum(value = "3650")))]
+        within_days: Query<Option<i32>>,
+    ) -> Result<Json<ProjectedWaste>> {
+        let within_days = within_days.0.unwrap_or(30);
+        let items = sqlx::query!(
+            r#"
+WITH consumption AS (
+    SELECT COALESCE(e.product_id, si.product_id) AS product_id,
+        SUM(e.stock_quantity)::float8 / 30 AS daily_rate
+    FROM stock_entries e
+    LEFT JOIN stock_items si ON si.id = e.stock_item_id
+    WHERE e.entry_type = 'consume' AND e.status = 'confirmed'
+      AND e.entry_timestamp >= LOCALTIMESTAMP - make_interval(days => 30)
+    GROUP BY 1
+)
+SELECT si.product_id, p.name, si.stock_quantity,
+    GREATEST(si.best_by_date - CURRENT_DATE, 0) AS "days_until_expiry!",
+    COALESCE(c.daily_rate, 0) AS "daily_rate!",
+    lp.price::numeric::float8 AS unit_price, u.decimal_places AS "decimal_places?"
+FROM stock_items si
+JOIN products p ON p.id = si.product_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+LEFT JOIN consumption c ON c.product_id = si.product_id
+LEFT JOIN latest_purchase_prices lp ON lp.product_id = si.product_id
+WHERE si.stock_quantity > 0 AND si.best_by_date <= CURRENT_DATE + $1::integer
+ORDER BY si.product_id, si.best_by_date"#,
+            within_days
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let mut products: Vec<ProjectedWasteProduct> = Vec::new();
+        let mut decimal_places = HashMap::new();
+        // The quantity of the current product already consumed from earlier expiring items
+        let mut consumed = 0.0;
+        for item in items {
+            let product = match products.last_mut() {
+                Some(product) if product.product_id == item.product_id => product,
+                _ => {
+                    consumed = 0.0;
+                    decimal_places.insert(item.product_id, item.decimal_places);
+                    products.push(ProjectedWasteProduct {
+                        product_id: item.product_id,
+                        name: item.name,
+                        daily_rate: item.daily_rate,
+                        expiring_quantity: 0.0,
+                        projected_waste_quantity: 0.0,
+                        unit_price: item.unit_price,
+                        projected_waste_value: item.unit_price.map(|_| 0.0),
+                    });
+                    products.last_mut().unwrap()
+                }
+            };
+
+            let quantity = f64::from(item.stock_quantity);
+            let consumable = item.daily_rate * f64::from(item.days_until_expiry) - consumed;
+            let consumed_in_time = quantity.min(consumable.max(0.0));
+            consumed += consumed_in_time;
+            product.expiring_quantity += quantity;
+            product.projected_waste_quantity += quantity - consumed_in_time;
+        }
+
+        let mut products: Vec<ProjectedWasteProduct> = products
+            .into_iter()
+            .filter(|product| product.projected_waste_quantity > 0.0)
+            .map(|product| {
+                let decimal_places = decimal_places[&product.product_id];
+                ProjectedWasteProduct {
+                    projected_waste_value: product
+                        .unit_price
+                        .map(|price| price * product.projected_waste_quantity),
+                    expiring_quantity: round_to_unit(product.expiring_quantity, decimal_places),
+                    projected_waste_quantity: round_to_unit(
+                        product.projected_waste_quantity,
+                        decimal_places,
+                    ),
+                    ..product
+                }
+            })
+            .collect();
+        products.sort_by(|a, b| {
+            let value =
+                |product: &ProjectedWasteProduct| product.projected_waste_value.unwrap_or(0.0);
+            value(b).total_cmp(&value(a))
+        });
+
+        Ok(Json(ProjectedWaste {
+            within_days,
+            total_value: products
+                .iter()
+                .filter_map(|product| product.projected_waste_value)
+                .sum(),
+            products,
+        }))
+    }
+
+    // TRASH
+    /// Trash: Fetch recently deleted rows
+    ///
+    /// Lists the rows of the given kind deleted within the last given number of days, most
+    /// recently deleted first. They can be restored through their `restore` endpoints.
+    #[oai(path = "/trash", method = "get")]
+    async fn get_trash(
+        &self,
+        pool: Data<&PgPool>,
+        /// The kind of rows to list
+        entity: Query<DeletableEntity>,
+        /// The number of days to look back over (defaults to 30)
+        #[oai(validator(minimum(value = "0")))]
+        within_days: Query<Option<i32>>,
+    ) -> Result<GetAllResponse<UnitConversion>> {
+        let within_days = within_days.0.unwrap_or(30);
+        let rows = match entity.0 {
+            DeletableEntity::UnitConversion => sqlx::query_as!(
+                UnitConversion,
+                r#"
+SELECT *
+FROM unit_conversions
+WHERE deleted_at >= LOCALTIMESTAMP - make_interval(days => $1)
+ORDER BY deleted_at DESC, id"#,
+                within_days
+            )
+            .fetch_all(pool.0)
+            .await
+            .map_err(InternalServerError)?,
+        };
+
+        Ok(Json(rows))
+    }
+
+    /// Trash: Restore a deleted row
+    ///
+    /// Fails if the row isn't deleted, or if restoring it would conflict with the rows that
+    /// replaced it. Unit conversions are checked like `/unit_conversions/:id/restore` does.
+    #[oai(path = "/restore/:entity/:id", method = "post")]
+    async fn restore(
+        &self,
+        pool: Data<&PgPool>,
+        path: Path<(String, i32)>,
+    ) -> Result<RestoreResponse> {
+        let Path((entity, id)) = path;
+        let Ok(entity) = DeletableEntity::parse_from_parameter(&entity) else {
+            return Ok(RestoreResponse::BadRequest(PlainText(format!(
+                "Unknown entity '{entity}', expected 'unit_conversion'."
+            ))));
+        };
+
+        match entity {
+            DeletableEntity::UnitConversion => {
+                let mut tx = pool.0.begin().await.map_err(InternalServerError)?;
+                let response = restore_unit_conversion(&mut tx, id).await?;
+                if let RestoreResponse::Success(_) = response {
+                    tx.commit().await.map_err(InternalServerError)?;
+                }
+
+                Ok(response)
+            }
+        }
+    }
+
+    // META
+    /// Meta: Fetch the stock entry types
+    ///
+    /// Lists every `StockEntry.entry_type` with a label and how it changes the stock.
+    #[oai(path = "/meta/entry_types", method = "get")]
+    async fn get_entry_types(&self) -> Json<Vec<EntryTypeInfo>> {
+        let entry_types = [
+            EntryType::Purchase,
+            EntryType::Transfer,
+            EntryType::Consume,
+            EntryType::Expire,
+            EntryType::Adjust,
+        ];
+
+        Json(
+            entry_types
+                .into_iter()
+                .map(|entry_type| EntryTypeInfo {
+                    label: entry_type.label().to_string(),
+                    effect: entry_type.effect(),
+                    value: entry_type,
+                })
+                .collect(),
+        )
+    }
+
+    // SEARCH
+    /// Search: Products, spaces and places by name or description
+    ///
+    /// Results are ordered by relevance: exact name matches first, then names starting with the
+    /// query, names containing it, and lastly descriptions containing it.
+    #[oai(path = "/search", method = "get")]
+    async fn search(
+        &self,
+        pool: Data<&PgPool>,
+        #[oai(validator(min_length = 1))] q: Query<String>,
+        /// The maximum number of results per type (defaults to 10)
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i64>>,
+        /// Whether to include archived products
+        include_archived: Query<Option<bool>>,
+    ) -> Result<Json<Vec<SearchResult>>> {
+        let records = sqlx::query!(
+            r#"
+(SELECT 'product' AS "kind!", id AS "id!", name AS "name!",
+    CASE
+        WHEN lower(name) = lower($1) THEN 0
+        WHEN name ILIKE substr($2, 2) THEN 1
+        WHEN name ILIKE $2 THEN 2
+        ELSE 3
+    END AS "rank!"
+FROM products
+WHERE (name ILIKE $2 OR description ILIKE $2) AND ($4 OR NOT archived)
+ORDER BY 4, name
+LIMIT $3)
+UNION ALL
+(SELECT 'space', id, name,
+    CASE
+        WHEN lower(name) = lower($1) THEN 0
+        WHEN name ILIKE substr($2, 2) THEN 1
+        WHEN name ILIKE $2 THEN 2
+        ELSE 3
+    END
+FROM spaces
+WHERE name ILIKE $2 OR description ILIKE $2
+ORDER BY 4, name
+LIMIT $3)
+UNION ALL
+(SELECT 'place', id, name,
+    CASE
+        WHEN lower(name) = lower($1) THEN 0
+        WHEN name ILIKE substr($2, 2) THEN 1
+        WHEN name ILIKE $2 THEN 2
+        ELSE 3
+    END
+FROM places
+WHERE name ILIKE $2 OR description ILIKE $2
+ORDER BY 4, name
+LIMIT $3)
+ORDER BY 4, 3"#,
+            q.0,
+            like_pattern(&q.0),
+            limit.0.unwrap_or(10),
+            include_archived.0.unwrap_or(false)
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let results = records
+            .into_iter()
+            .map(|record| SearchResult {
+                result_type: match record.kind.as_str() {
+                    "product" => SearchResultType::Product,
+                    "space" => SearchResultType::Space,
+                    _ => SearchResultType::Place,
+                },
+                id: record.id,
+                name: record.name,
+                rank: record.rank,
+            })
+            .collect();
+
+        Ok(Json(results))
+    }
+
+    /// Reports: Inventory turnover per product
+    ///
+    /// The turnover is the quantity consumed within the (inclusive) date window, divided by the
+    /// average of the stock at its start and end. Past stock levels are reconstructed from the
+    /// current stock and the confirmed stock entries since.
+    ///
+    /// Reports are cached for `TURNOVER_CACHE_TTL` seconds per date window.
+    #[oai(path = "/reports/turnover", method = "get")]
+    async fn get_turnover_report(
+        &self,
+        pool: Data<&PgPool>,
+        from: Query<NaiveDate>,
+        to: Query<NaiveDate>,
+        /// Compute the report even if a cached one is available
+        refresh: Query<Option<bool>>,
+    ) -> Result<CachedReportResponse<Vec<ProductTurnover>>> {
+        if from.0 > to.0 {
+            return Ok(CachedReportResponse::BadRequest(PlainText(format!(
+                "The start of the period '{}' is after its end '{}'.",
+                from.0, to.0
+            ))));
+        }
+        let key = format!("{}/{}", from.0, to.0);
+        if !refresh.0.unwrap_or(false) {
+            if let Some((turnover, cached_at)) = self.turnover_cache.get(&key) {
+                return Ok(CachedReportResponse::Success(
+                    Json(turnover),
+                    cached_at.to_rfc3339(),
+                    false,
+                ));
+            }
+        }
+
+        let records = sqlx::query!(
+            r#"
+WITH current_stock AS (
+    SELECT product_id, SUM(stock_quantity)::float8 AS quantity
+    FROM stock_items
+    GROUP BY product_id
+), changes AS (
+    SELECT COALESCE(e.product_id, si.product_id) AS product_id, e.entry_timestamp, e.entry_type,
+        CASE e.entry_type
+            WHEN 'purchase' THEN e.stock_quantity
+            WHEN 'adjust' THEN e.stock_quantity
+            WHEN 'transfer' THEN 0
+            ELSE -e.stock_quantity
+        END::float8 AS delta,
+        e.stock_quantity::float8 AS quantity
+    FROM stock_entries e
+    LEFT JOIN stock_items si ON si.id = e.stock_item_id
+    WHERE e.status = 'confirmed'
+)
+SELECT p.id, p.name, u.decimal_places AS "decimal_places?",
+    COALESCE(SUM(c.quantity) FILTER (
+        WHERE c.entry_type = 'consume' AND c.entry_timestamp >= $1::date AND c.entry_timestamp < $2::date + 1
+    ), 0) AS "consumed!",
+    COALESCE(cs.quantity, 0) - COALESCE(SUM(c.delta) FILTER (WHERE c.entry_timestamp >= $1::date), 0) AS "opening_stock!",
+    COALESCE(cs.quantity, 0) - COALESCE(SUM(c.delta) FILTER (WHERE c.entry_timestamp >= $2::date + 1), 0) AS "closing_stock!"
+FROM products p
+LEFT JOIN units u ON u.id = p.stock_unit_id
+LEFT JOIN current_stock cs ON cs.product_id = p.id
+LEFT JOIN changes c ON c.product_id = p.id
+GROUP BY p.id, u.decimal_places, cs.quantity
+ORDER BY p.id"#,
+            from.0,
+            to.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        let turnover = records
+            .into_iter()
+            .map(|record| {
+                let average_stock = (record.opening_stock + record.closing_stock) / 2.0;
+                let round = |quantity| round_to_unit(quantity, record.decimal_places);
+
+                ProductTurnover {
+                    product_id: record.id,
+                    name: record.name,
+                    consumed: round(record.consumed),
+                    opening_stock: round(record.opening_stock),
+                    closing_stock: round(record.closing_stock),
+                    average_stock: round(average_stock),
+                    turnover_ratio: (average_stock > 0.0).then(|| record.consumed / average_stock),
+                }
+            })
+            .collect::<Vec<_>>();
+        let cached_at = self.turnover_cache.insert(key, turnover.clone());
+
+        Ok(CachedReportResponse::Success(
+            Json(turnover),
+            cached_at.to_rfc3339(),
+            true,
+        ))
+    }
+
+    /// Reports: Stock items by value
+    ///
+    /// Values stock items by the latest purchase price of their product, most valuable first.
+    /// Items without a known price are listed last. Reports are cached for
+    /// `STOCK_BY_VALUE_CACHE_TTL` seconds.
+    #[oai(path = "/reports/stock_by_value", method = "get")]
+    async fn get_stock_by_value_report(
+        &self,
+        pool: Data<&PgPool>,
+        /// Compute the report even if a cached one is available
+        refresh: Query<Option<bool>>,
+    ) -> Result<CachedReportResponse<Vec<StockItemValue>>> {
+        if !refresh.0.unwrap_or(false) {
+            if let Some((items, cached_at)) = self.stock_by_value_cache.get("") {
+                return Ok(CachedReportResponse::Success(
+                    Json(items),
+                    cached_at.to_rfc3339(),
+                    false,
+                ));
+            }
+        }
+
+        let items = sqlx::query_as!(
+            StockItemValue,
+            r#"
+SELECT si.id, si.product_id, p.name AS product_name, si.space_id, s.name AS space_name,
+    round_quantity(si.stock_quantity, u.decimal_places) AS "stock_quantity!",
+    lp.price::numeric::float8 AS unit_price,
+    (si.stock_quantity::numeric * lp.price::numeric)::float8 AS value,
+    lp.price IS NOT NULL AS "priced!"
+FROM stock_items si
+JOIN products p ON p.id = si.product_id
+JOIN spaces s ON s.id = si.space_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+LEFT JOIN latest_purchase_prices lp ON lp.product_id = si.product_id
+ORDER BY value DESC NULLS LAST, si.id"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+        let cached_at = self
+            .stock_by_value_cache
+            .insert(String::new(), items.clone());
+
+        Ok(CachedReportResponse::Success(
+            Json(items),
+            cached_at.to_rfc3339(),
+            true,
+        ))
+    }
+
+    /// Reports: Stale stock
+    ///
+    /// Lists the stock items in stock for longer than the threshold, whatever their best by date,
+    /// oldest first.
+    #[oai(path = "/reports/stale_stock", method = "get")]
+    async fn get_stale_stock_report(
+        &self,
+        pool: Data<&PgPool>,
+        /// The number of days after which stock is stale (defaults to 90)
+        #[oai(validator(minimum(value = "0")))]
+        older_than_days: Query<Option<i32>>,
+    ) -> Result<GetAllResponse<StaleStockItem>> {
+        let items = sqlx::query_as!(
+            StaleStockItem,
+            r#"
+SELECT si.id, si.product_id, p.name AS product_name, si.space_id, s.name AS space_name,
+    round_quantity(si.stock_quantity, u.decimal_places) AS "stock_quantity!",
+    si.best_by_date, si.created_at,
+    (CURRENT_DATE - si.created_at::date) AS "age_days!"
+FROM stock_items si
+JOIN products p ON p.id = si.product_id
+JOIN spaces s ON s.id = si.space_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+WHERE si.stock_quantity > 0
+  AND si.created_at < LOCALTIMESTAMP - make_interval(days => $1)
+ORDER BY si.created_at, si.id"#,
+            older_than_days.0.unwrap_or(90)
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(Json(items))
+    }
+
+    /// Reports: Products with stock outside of their range
+    ///
+    /// Lists the products whose total stock is below their minimum or above their maximum stock.
+    #[oai(path = "/reports/out_of_range", method = "get")]
+    async fn get_out_of_range_report(
+        &self,
+        pool: Data<&PgPool>,
+    ) -> Result<GetAllResponse<OutOfRangeProduct>> {
+        let records = sqlx::query!(
+            r#"
+SELECT p.id, p.name, p.min_stock, p.max_stock, u.decimal_places AS "decimal_places?",
+    COALESCE(SUM(si.stock_quantity), 0)::float8 AS "total_quantity!"
+FROM products p
+LEFT JOIN units u ON u.id = p.stock_unit_id
+LEFT JOIN stock_items si ON si.product_id = p.id
+WHERE p.min_stock IS NOT NULL OR p.max_stock IS NOT NULL
+GROUP BY p.id, u.decimal_places
+ORDER BY p.id"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
 
-        match result {
-            Some(_) => Ok(DeleteResponse::Success(Json(id.0))),
-            None => Ok(DeleteResponse::NotFound(PlainText(
-                format!("No unit with id '{}' found.", id.0),
-            ))),
-        }
+        let products = records
+            .into_iter()
+            .filter_map(|record| {
+                let status = if record
+                    .min_stock
+                    .is_some_and(|min_stock| record.total_quantity < f64::from(min_stock))
+                {
+                    StockRangeStatus::BelowMin
+                } else if record
+                    .max_stock
+                    .is_some_and(|max_stock| record.total_quantity > f64::from(max_stock))
+                {
+                    StockRangeStatus::AboveMax
+                } else {
+                    return None;
+                };
+
+                Some(OutOfRangeProduct {
+                    product_id: record.id,
+                    name: record.name,
+                    total_quantity: round_to_unit(record.total_quantity, record.decimal_places),
+                    min_stock: record.min_stock,
+                    max_stock: record.max_stock,
+                    status,
+                })
+            })
+            .collect();
+
+        Ok(Json(products))
     }
 
-    // UNIT CONVERSIONS
-    /// Unit Conversions: Fetch all
-    #[oai(path = "/unit_conversions", method = "get")]
-    async fn get_unit_conversions(
-        &self,
-        pool: Data<&PgPool>,
-    ) -> Result<GetAllResponse<UnitConversion>> {
-        let unit_conversions = sqlx::query_as!(UnitConversion, "SELECT * FROM unit_conversions")
-            .fetch_all(pool.0)
-            .await
-            .unwrap();
+    /// Reports: Estimated cost of restocking
+    ///
+    /// Prices the quantities needed to bring every product below its minimum stock back up to its
+    /// maximum stock (or its minimum stock, if it has no maximum), based on the latest purchase
+    /// price of each product.
+    #[oai(path = "/reports/restock_cost", method = "get")]
+    async fn get_restock_cost_report(&self, pool: Data<&PgPool>) -> Result<Json<RestockCost>> {
+        let products = sqlx::query_as!(
+            RestockProduct,
+            r#"
+WITH totals AS (
+    SELECT p.id, p.name, p.stock_unit_id,
+        COALESCE(p.max_stock, p.min_stock)::float8 AS target_quantity,
+        COALESCE(SUM(si.stock_quantity), 0)::float8 AS total_quantity
+    FROM products p
+    LEFT JOIN stock_items si ON si.product_id = p.id
+    WHERE p.min_stock IS NOT NULL
+    GROUP BY p.id
+    HAVING COALESCE(SUM(si.stock_quantity), 0) < p.min_stock
+)
+SELECT t.id AS product_id, t.name,
+    round_quantity(t.total_quantity, u.decimal_places) AS "total_quantity!",
+    t.target_quantity AS "target_quantity!",
+    round_quantity(t.target_quantity - t.total_quantity, u.decimal_places) AS "reorder_quantity!",
+    lp.price::numeric::float8 AS unit_price,
+    ((t.target_quantity - t.total_quantity)::numeric * lp.price::numeric)::float8 AS estimated_cost,
+    lp.price IS NOT NULL AS "priced!"
+FROM totals t
+LEFT JOIN units u ON u.id = t.stock_unit_id
+LEFT JOIN latest_purchase_prices lp ON lp.product_id = t.id
+ORDER BY t.id"#
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
 
-        Ok(Json(unit_conversions))
+        let total_cost = products
+            .iter()
+            .filter_map(|product| product.estimated_cost)
+            .sum();
+        let unpriced_product_ids = products
+            .iter()
+            .filter(|product| !product.priced)
+            .map(|product| product.product_id)
+            .collect();
+
+        Ok(Json(RestockCost {
+            products,
+            total_cost,
+            unpriced_product_ids,
+        }))
     }
 
-    /// Unit Conversions: Fetch by id
-    #[oai(path = "/unit_conversions/:id", method = "get")]
-    async fn get_unit_conversion(
+    /// Reports: Purchases needed to reach target stock levels
+    ///
+    /// Subtracts the current stock from each target, converting the shortfall to purchase units
+    /// and rounding it up to whole units. The product's own `purchase_to_stock_factor` is used
+    /// for the conversion when set, the unit conversions otherwise.
+    #[oai(
+        path = "/reports/purchase_plan",
+        method = "post",
+        tag = "ApiTags::Reading"
+    )]
+    async fn get_purchase_plan_report(
         &self,
         pool: Data<&PgPool>,
-        id: Path<i32>,
-    ) -> Result<GetResponse<UnitConversion>> {
-        let unit_conversion: Option<UnitConversion> = sqlx::query_as!(
-            UnitConversion,
-            "SELECT * FROM unit_conversions WHERE id = $1",
-            id.0
+        targets: Json<Vec<StockTarget>>,
+    ) -> Result<GetResponse<PurchasePlan>> {
+        let (product_ids, target_quantities): (Vec<i32>, Vec<f64>) = targets
+            .iter()
+            .map(|target| (target.product_id, target.target_quantity))
+            .unzip();
+        let records = sqlx::query!(
+            r#"
+SELECT t.product_id AS "product_id!", t.target_quantity AS "target_quantity!",
+    p.name AS "name?", p.purchase_unit_id, p.stock_unit_id, p.purchase_to_stock_factor,
+    u.decimal_places AS "decimal_places?",
+    (SELECT COALESCE(SUM(si.stock_quantity), 0)::float8
+        FROM stock_items si
+        WHERE si.product_id = t.product_id) AS "total_quantity!",
+    lp.price::numeric::float8 AS unit_price
+FROM unnest($1::integer[], $2::float8[]) WITH ORDINALITY AS t(product_id, target_quantity, position)
+LEFT JOIN products p ON p.id = t.product_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+LEFT JOIN latest_purchase_prices lp ON lp.product_id = t.product_id
+ORDER BY t.position"#,
+            &product_ids,
+            &target_quantities
         )
-        .fetch_optional(pool.0)
+        .fetch_all(pool.0)
         .await
         .map_err(InternalServerError)?;
 
-        match unit_conversion {
-            Some(uc) => Ok(GetResponse::Success(Json(uc))),
-            None => Ok(GetResponse::NotFound(PlainText(
-                format!("No unit conversion with id '{}' found.", id.0),
-            ))),
+        let graph = ConversionGraph::load(pool.0, self.max_conversion_path_length)
+            .await
+            .map_err(InternalServerError)?;
+        let mut products = Vec::with_capacity(records.len());
+        for record in records {
+            let Some(name) = record.name else {
+                return Ok(GetResponse::NotFound(PlainText(format!(
+                    "No product with id '{}' found.",
+                    record.product_id
+                ))));
+            };
+
+            let factor = match (
+                record.purchase_to_stock_factor,
+                record.purchase_unit_id,
+                record.stock_unit_id,
+            ) {
+                (Some(factor), _, _) => Some(f64::from(factor)),
+                (None, Some(purchase_unit_id), Some(stock_unit_id)) => {
+                    graph.factor(purchase_unit_id, stock_unit_id).ok()
+                }
+                _ => None,
+            }
+            .filter(|&factor| factor > 0.0);
+            let shortfall = (record.target_quantity - record.total_quantity).max(0.0);
+            // Rounded first, so the noise of the division doesn't round up a whole unit
+            let purchase_quantity =
+                factor.map(|factor| round_half_even(shortfall / factor, 6).ceil());
+            let bought_quantity = match (purchase_quantity, factor) {
+                (Some(purchase_quantity), Some(factor)) => purchase_quantity * factor,
+                _ => shortfall,
+            };
+
+            products.push(PlannedPurchase {
+                product_id: record.product_id,
+                name,
+                total_quantity: round_to_unit(record.total_quantity, record.decimal_places),
+                target_quantity: record.target_quantity,
+                shortfall: round_to_unit(shortfall, record.decimal_places),
+                purchase_unit_id: record.purchase_unit_id,
+                purchase_quantity,
+                unit_price: record.unit_price,
+                estimated_cost: record.unit_price.map(|price| bought_quantity * price),
+            });
         }
+
+        let total_cost = products
+            .iter()
+            .filter_map(|product| product.estimated_cost)
+            .sum();
+        let unpriced_product_ids = products
+            .iter()
+            .filter(|product| product.unit_price.is_none())
+            .map(|product| product.product_id)
+            .collect();
+
+        Ok(GetResponse::Success(Json(PurchasePlan {
+            products,
+            total_cost,
+            unpriced_product_ids,
+        })))
     }
 
-    /// Unit Conversions: Create new
-    #[oai(path = "/unit_conversions", method = "post")]
-    async fn new_unit_conversion(
+    /// Reports: Stock items with negative stock
+    ///
+    /// Stock should never go negative, so any items listed here point to a bug or a direct edit
+    /// of the database. They can be zeroed through `/maintenance/clamp_negative_stock`.
+    #[oai(path = "/reports/negative_stock", method = "get")]
+    async fn get_negative_stock_report(
         &self,
         pool: Data<&PgPool>,
-        conversion: Json<UnitConversion>,
-    ) -> Result<Json<i32>> {
-        let record = sqlx::query!(
+    ) -> Result<GetAllResponse<StockItemDetail>> {
+        let items = sqlx::query_as!(
+            StockItemDetail,
             r#"
-INSERT INTO unit_conversions (from_unit_id, to_unit_id, factor)
-VALUES ($1, $2, $3)
-RETURNING id"#,
-            conversion.from_unit_id,
-            conversion.to_unit_id,
-            conversion.factor,
+SELECT si.id, si.product_id, p.name AS product_name, si.space_id, s.name AS space_name,
+    si.stock_quantity,
+    p.stock_unit_id,
+    si.stock_quantity / NULLIF(p.purchase_to_stock_factor, 0) AS purchase_unit_quantity,
+    p.purchase_unit_id,
+    si.best_by_date,
+    format_quantity(si.stock_quantity, u.singular, u.plural) AS display
+FROM stock_items si
+JOIN products p ON p.id = si.product_id
+JOIN spaces s ON s.id = si.space_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+WHERE si.stock_quantity < 0
+ORDER BY si.stock_quantity, si.id"#
         )
-        .fetch_one(pool.0)
+        .fetch_all(pool.0)
         .await
         .map_err(InternalServerError)?;
 
-        Ok(Json(record.id))
+        Ok(Json(items))
     }
 
-    /// Units: Delete with id
-    #[oai(path = "/unit_conversions/:id", method = "delete")]
-    async fn delete_unit_conversion(
+    /// Reports: Product diversity per space
+    ///
+    /// Counts the distinct products and stock items in each space, most diverse first.
+    #[oai(path = "/reports/space_diversity", method = "get")]
+    async fn get_space_diversity_report(
         &self,
         pool: Data<&PgPool>,
-        id: Path<i32>,
-    ) -> Result<DeleteResponse> {
-        let result = sqlx::query!(
+    ) -> Result<GetAllResponse<SpaceDiversity>> {
+        let spaces = sqlx::query_as!(
+            SpaceDiversity,
             r#"
-DELETE FROM unit_conversions
-WHERE id = $1
-RETURNING id"#,
-            id.0
+SELECT s.id AS space_id, s.name,
+    COUNT(DISTINCT si.product_id) AS "product_count!",
+    COUNT(si.id) AS "stock_item_count!"
+FROM spaces s
+LEFT JOIN stock_items si ON si.space_id = s.id
+GROUP BY s.id
+ORDER BY COUNT(DISTINCT si.product_id) DESC, COUNT(si.id) DESC, s.id"#
         )
-        .fetch_optional(pool.0)
+        .fetch_all(pool.0)
         .await
         .map_err(InternalServerError)?;
 
-        match result {
-            Some(_) => Ok(DeleteResponse::Success(Json(id.0))),
-            None => Ok(DeleteResponse::NotFound(PlainText(
-                format!("No unit conversion with id '{}' found.", id.0),
-            ))),
-        }
-    }
-
-    // PLACES
-    /// Places: Fetch all
-    #[oai(path = "/places", method = "get")]
-    async fn get_places(&self, pool: Data<&PgPool>) -> Result<GetAllResponse<Place>> {
-        let places = sqlx::query_as!(Place, "SELECT * FROM places")
-            .fetch_all(pool.0)
-            .await
-            .map_err(InternalServerError)?;
-
-        Ok(Json(places))
+        Ok(Json(spaces))
     }
 
-    /// Places: Fetch by id
-    #[oai(path = "/places/:id", method = "get")]
-    async fn get_place(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<GetResponse<Place>> {
-        let result: Option<Place> =
-            sqlx::query_as!(Place, "SELECT * FROM places WHERE id = $1", id.0)
-                .fetch_optional(pool.0)
-                .await
-                .map_err(InternalServerError)?;
-
-        match result {
-            Some(place) => Ok(GetResponse::Success(Json(place))),
-            None => Ok(GetResponse::NotFound(PlainText(
-                format!("No place with id '{}' found.", id.0),
-            ))),
+    /// Reports: Most consumed products
+    ///
+    /// Sums the `Consume` stock entries within the (inclusive) date window per product, most
+    /// consumed first.
+    #[oai(path = "/reports/top_consumed", method = "get")]
+    async fn get_top_consumed_report(
+        &self,
+        pool: Data<&PgPool>,
+        from: Query<Option<NaiveDate>>,
+        to: Query<Option<NaiveDate>>,
+        /// The maximum number of products (defaults to 10)
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i64>>,
+    ) -> Result<ReportResponse<Vec<ConsumedProduct>>> {
+        if let (Some(from), Some(to)) = (from.0, to.0) {
+            if from > to {
+                return Ok(ReportResponse::BadRequest(PlainText(format!(
+                    "The start of the period '{from}' is after its end '{to}'."
+                ))));
+            }
         }
-    }
 
-    /// Places: Create new
-    #[oai(path = "/place", method = "post")]
-    async fn new_place(&self, pool: Data<&PgPool>, place: Json<Place>) -> Result<Json<i32>> {
-        let record = sqlx::query!(
+        let products = sqlx::query_as!(
+            ConsumedProduct,
             r#"
-INSERT INTO places (name, description)
-VALUES ($1, $2)
-RETURNING id"#,
-            place.name,
-            place.description,
+WITH consumption AS (
+    SELECT COALESCE(e.product_id, si.product_id) AS product_id, e.stock_quantity
+    FROM stock_entries e
+    LEFT JOIN stock_items si ON si.id = e.stock_item_id
+    WHERE e.entry_type = 'consume' AND e.status = 'confirmed'
+      AND ($1::date IS NULL OR e.entry_timestamp >= $1::date)
+      AND ($2::date IS NULL OR e.entry_timestamp < $2::date + 1)
+)
+SELECT p.id AS product_id, p.name, p.stock_unit_id,
+    COUNT(*) AS "entry_count!",
+    round_quantity(SUM(c.stock_quantity)::float8, u.decimal_places) AS "total_quantity!"
+FROM consumption c
+JOIN products p ON p.id = c.product_id
+LEFT JOIN units u ON u.id = p.stock_unit_id
+GROUP BY p.id, u.decimal_places
+ORDER BY SUM(c.stock_quantity) DESC, p.id
+LIMIT $3"#,
+            from.0,
+            to.0,
+            limit.0.unwrap_or(10)
         )
-        .fetch_one(pool.0)
+        .fetch_all(pool.0)
         .await
         .map_err(InternalServerError)?;
 
-        Ok(Json(record.id))
+        Ok(ReportResponse::Success(Json(products)))
     }
 
-    /// Places: Delete with id
-    #[oai(path = "/places/:id", method = "delete")]
-    async fn delete_place(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<DeleteResponse> {
-        let result = sqlx::query!(
+    /// Reports: Shrinkage since a snapshot
+    ///
+    /// Compares the stock each product should have, going by its snapshot total and the
+    /// confirmed stock entries since, with the stock it actually has. Defaults to the latest
+    /// snapshot.
+    #[oai(path = "/reports/shrinkage", method = "get")]
+    async fn get_shrinkage_report(
+        &self,
+        pool: Data<&PgPool>,
+        /// The id of any row of the snapshot to compare against
+        since_snapshot: Query<Option<i32>>,
+    ) -> Result<GetResponse<Shrinkage>> {
+        let taken_at = sqlx::query_scalar!(
             r#"
-DELETE FROM places
-WHERE id = $1
-RETURNING id"#,
-            id.0
+SELECT taken_at
+FROM inventory_snapshots
+WHERE $1::integer IS NULL OR id = $1
+ORDER BY taken_at DESC
+LIMIT 1"#,
+            since_snapshot.0
         )
         .fetch_optional(pool.0)
         .await
         .map_err(InternalServerError)?;
+        let Some(taken_at) = taken_at else {
+            return Ok(GetResponse::NotFound(PlainText(match since_snapshot.0 {
+                Some(id) => format!("No snapshot with id '{id}' found."),
+                None => "No snapshot has been taken yet.".to_string(),
+            })));
+        };
 
-        match result {
-            Some(_) => Ok(DeleteResponse::Success(Json(id.0))),
-            None => Ok(DeleteResponse::NotFound(PlainText(
-                format!("No place with id '{}' found.", id.0),
-            ))),
-        }
-    }
+        let records = sqlx::query!(
+            r#"
+WITH snapshot AS (
+    SELECT product_id, total_quantity
+    FROM inventory_snapshots
+    WHERE taken_at = $1 AND space_id IS NULL
+), entries AS (
+    SELECT COALESCE(e.product_id, si.product_id) AS product_id,
+        SUM(CASE
+            WHEN e.entry_type IN ('purchase', 'adjust') THEN e.stock_quantity
+            WHEN e.entry_type IN ('consume', 'expire') THEN -e.stock_quantity
+            ELSE 0
+        END)::float8 AS quantity
+    FROM stock_entries e
+    LEFT JOIN stock_items si ON si.id = e.stock_item_id
+    WHERE e.status = 'confirmed' AND e.entry_timestamp > $1
+    GROUP BY 1
+), actual AS (
+    SELECT product_id, SUM(stock_quantity)::float8 AS quantity
+    FROM stock_items
+    GROUP BY product_id
+)
+SELECT p.id, p.name, u.decimal_places AS "decimal_places?",
+    COALESCE(s.total_quantity, 0) AS "snapshot_quantity!",
+    COALESCE(e.quantity, 0) AS "net_entry_quantity!",
+    COALESCE(a.quantity, 0) AS "actual_quantity!"
+FROM products p
+LEFT JOIN units u ON u.id = p.stock_unit_id
+LEFT JOIN snapshot s ON s.product_id = p.id
+LEFT JOIN entries e ON e.product_id = p.id
+LEFT JOIN actual a ON a.product_id = p.id
+ORDER BY p.id"#,
+            taken_at
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
 
-    // SPACES
-    /// Spaces: Fetch all
-    #[oai(path = "/spaces", method = "get")]
-    async fn get_spaces(&self, pool: Data<&PgPool>) -> Result<GetAllResponse<Space>> {
-        let spaces = sqlx::query_as!(Space, "SELECT * FROM spaces")
-            .fetch_all(pool.0)
-            .await
-            .map_err(InternalServerError)?;
+        let mut products: Vec<ProductShrinkage> = records
+            .into_iter()
+            .map(|record| {
+                let expected_quantity = record.snapshot_quantity + record.net_entry_quantity;
+                let round = |quantity| round_to_unit(quantity, record.decimal_places);
+                ProductShrinkage {
+                    product_id: record.id,
+                    name: record.name,
+                    snapshot_quantity: round(record.snapshot_quantity),
+                    net_entry_quantity: round(record.net_entry_quantity),
+                    expected_quantity: round(expected_quantity),
+                    actual_quantity: round(record.actual_quantity),
+                    discrepancy: round(expected_quantity - record.actual_quantity),
+                }
+            })
+            .filter(|product| product.discrepancy.abs() > 1e-6)
+            .collect();
+        products.sort_by(|a, b| b.discrepancy.abs().total_cmp(&a.discrepancy.abs()));
 
-        Ok(Json(spaces))
+        Ok(GetResponse::Success(Json(Shrinkage {
+            snapshot_taken_at: taken_at,
+            products,
+        })))
     }
 
-    /// Spaces: Fetch by id
-    #[oai(path = "/spaces/:id", method = "get")]
-    async fn get_space(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<GetResponse<Space>> {
-        let result: Option<Space> =
-            sqlx::query_as!(Space, "SELECT * FROM spaces WHERE id = $1", id.0)
-                .fetch_optional(pool.0)
-                .await
-                .map_err(InternalServerError)?;
-
-        match result {
-            Some(space) => Ok(GetResponse::Success(Json(space))),
-            None => Ok(GetResponse::NotFound(PlainText(
-                format!("No space with id '{}' found.", id.0),
-            ))),
-        }
-    }
+    /// Reports: Products to prioritize
+    ///
+    /// Scores every product that is expiring within the horizon or below its minimum stock by
+    /// combining how close it is to expiring with how short of its minimum stock it is, most
+    /// urgent first.
+    #[oai(path = "/reports/priority", method = "get")]
+    async fn get_priority_report(
+        &self,
+        pool: Data<&PgPool>,
+        /// The weight of closeness to expiry in the score (defaults to 1)
+        #[oai(validator(minimum(value = "0")))]
+        expiry_weight: Query<Option<f64>>,
+        /// The weight of the shortage in the score (defaults to 1)
+        #[oai(validator(minimum(value = "0")))]
+        shortage_weight: Query<Option<f64>>,
+        /// The number of days until the best by date from which stock counts as expiring
+        /// (defaults to 30)
+        #[oai(validator(minimum(value = "1")))]
+        horizon_days: Query<Option<i32>>,
+    ) -> Result<GetAllResponse<PriorityProduct>> {
+        let expiry_weight = expiry_weight.0.unwrap_or(1.0);
+        let shortage_weight = shortage_weight.0.unwrap_or(1.0);
+        let horizon_days = horizon_days.0.unwrap_or(30);
 
-    /// Spaces: Create new
-    #[oai(path = "/space", method = "post")]
-    async fn new_space(&self, pool: Data<&PgPool>, space: Json<Space>) -> Result<Json<i32>> {
-        let record = sqlx::query!(
+        let records = sqlx::query!(
             r#"
-INSERT INTO spaces (name, description)
-VALUES ($1, $2)
-RETURNING id"#,
-            space.name,
-            space.description,
+SELECT p.id, p.name, p.min_stock, u.decimal_places AS "decimal_places?",
+    (MIN(si.best_by_date) FILTER (WHERE si.stock_quantity > 0) - CURRENT_DATE) AS days_until_expiry,
+    COALESCE(SUM(si.stock_quantity), 0)::float8 AS "total_quantity!"
+FROM products p
+LEFT JOIN units u ON u.id = p.stock_unit_id
+LEFT JOIN stock_items si ON si.product_id = p.id
+GROUP BY p.id, u.decimal_places
+ORDER BY p.id"#
         )
-        .fetch_one(pool.0)
+        .fetch_all(pool.0)
         .await
         .map_err(InternalServerError)?;
 
-        Ok(Json(record.id))
+        let mut products: Vec<PriorityProduct> = records
+            .into_iter()
+            .filter_map(|record| {
+                let expiry_factor = record.days_until_expiry.map_or(0.0, |days| {
+                    (1.0 - f64::from(days) / f64::from(horizon_days)).clamp(0.0, 1.0)
+                });
+                let shortage_factor = shortage_factor(record.min_stock, record.total_quantity);
+                if expiry_factor == 0.0 && shortage_factor == 0.0 {
+                    return None;
+                }
+
+                Some(PriorityProduct {
+                    product_id: record.id,
+                    name: record.name,
+                    score: expiry_weight * expiry_factor + shortage_weight * shortage_factor,
+                    expiry_factor,
+                    shortage_factor,
+                    days_until_expiry: record.days_until_expiry,
+                    total_quantity: round_to_unit(record.total_quantity, record.decimal_places),
+                    min_stock: record.min_stock,
+                })
+            })
+            .collect();
+        products.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        Ok(Json(products))
     }
 
-    /// Spaces: Delete with id
-    #[oai(path = "/spaces/:id", method = "delete")]
-    async fn delete_space(&self, pool: Data<&PgPool>, id: Path<i32>) -> Result<DeleteResponse> {
-        let result = sqlx::query!(
+    /// Reports: Products to reorder, most urgent first
+    ///
+    /// Scores the products with a minimum stock by how far below it they are and how soon they
+    /// run out at their consumption rate, leaving out those scoring 0.
+    #[oai(path = "/reports/reorder_priority", method = "get")]
+    async fn get_reorder_priority_report(
+        &self,
+        pool: Data<&PgPool>,
+        /// The weight of the shortage in the score (defaults to 1)
+        #[oai(validator(minimum(value = "0")))]
+        shortage_weight: Query<Option<f64>>,
+        /// The weight of how soon the product runs out in the score (defaults to 1)
+        #[oai(validator(minimum(value = "0")))]
+        run_out_weight: Query<Option<f64>>,
+        /// The number of days of stock left from which a product counts as running out
+        /// (defaults to 14)
+        #[oai(validator(minimum(value = "1")))]
+        horizon_days: Query<Option<i32>>,
+        /// The number of days to average consumption over (defaults to 30)
+        #[oai(validator(minimum(value = "1"), maximum(value = "3650")))]
+        days: Query<Option<i32>>,
+    ) -> Result<GetAllResponse<ReorderPriorityProduct>> {
+        let shortage_weight = shortage_weight.0.unwrap_or(1.0);
+        let run_out_weight = run_out_weight.0.unwrap_or(1.0);
+        let horizon_days = horizon_days.0.unwrap_or(14);
+        let days = days.0.unwrap_or(30);
+
+        let records = sqlx::query!(
             r#"
-DELETE FROM spaces
-WHERE id = $1
-RETURNING id"#,
-            id.0
+WITH stock AS (
+    SELECT product_id, SUM(stock_quantity)::float8 AS quantity
+    FROM stock_items
+    GROUP BY product_id
+), consumption AS (
+    SELECT COALESCE(e.product_id, si.product_id) AS product_id,
+        SUM(e.stock_quantity)::float8 / $1::integer AS daily_rate
+    FROM stock_entries e
+    LEFT JOIN stock_items si ON si.id = e.stock_item_id
+    WHERE e.entry_type = 'consume' AND e.status = 'confirmed'
+      AND e.entry_timestamp >= LOCALTIMESTAMP - make_interval(days => $1::integer)
+    GROUP BY 1
+)
+SELECT p.id, p.name, p.min_stock AS "min_stock!", u.decimal_places AS "decimal_places?",
+    COALESCE(s.quantity, 0) AS "total_quantity!",
+    COALESCE(c.daily_rate, 0) AS "daily_rate!"
+FROM products p
+LEFT JOIN units u ON u.id = p.stock_unit_id
+LEFT JOIN stock s ON s.product_id = p.id
+LEFT JOIN consumption c ON c.product_id = p.id
+WHERE p.min_stock IS NOT NULL
+ORDER BY p.id"#,
+            days
         )
-        .fetch_optional(pool.0)
+        .fetch_all(pool.0)
         .await
         .map_err(InternalServerError)?;
 
-        match result {
-            Some(_) => Ok(DeleteResponse::Success(Json(id.0))),
-            None => Ok(DeleteResponse::NotFound(PlainText(
-                format!("No space with id '{}' found.", id.0),
-            ))),
-        }
-    }
+        let mut products: Vec<ReorderPriorityProduct> = records
+            .into_iter()
+            .filter_map(|record| {
+                let shortage_factor =
+                    shortage_factor(Some(record.min_stock), record.total_quantity);
+                let days_remaining = (record.daily_rate > 0.0)
+                    .then(|| record.total_quantity.max(0.0) / record.daily_rate);
+                let run_out_factor = days_remaining.map_or(0.0, |days_remaining| {
+                    (1.0 - days_remaining / f64::from(horizon_days)).clamp(0.0, 1.0)
+                });
+                let score = shortage_weight * shortage_factor + run_out_weight * run_out_factor;
+                if score == 0.0 {
+                    return None;
+                }
 
-    // STOCK ITEMS
-    /// Stock Items: Fetch all
-    #[oai(path = "/stock_items", method = "get")]
-    async fn get_stock_items(&self, pool: Data<&PgPool>) -> Result<GetAllResponse<StockItem>> {
-        let spaces = sqlx::query_as!(StockItem, "SELECT * FROM stock_items")
-            .fetch_all(pool.0)
-            .await
-            .map_err(InternalServerError)?;
+                Some(ReorderPriorityProduct {
+                    product_id: record.id,
+                    name: record.name,
+                    score,
+                    shortage_factor,
+                    run_out_factor,
+                    total_quantity: round_to_unit(record.total_quantity, record.decimal_places),
+                    min_stock: record.min_stock,
+                    daily_rate: record.daily_rate,
+                    days_remaining,
+                })
+            })
+            .collect();
+        products.sort_by(|a, b| b.score.total_cmp(&a.score));
 
-        Ok(Json(spaces))
+        Ok(Json(products))
     }
 
-    /// Stock Items: Fetch by id
-    #[oai(path = "/stock_items/:id", method = "get")]
-    async fn get_stock_item(
+    /// Reports: Spend per supplier
+    ///
+    /// Sums the price of the `Purchase` stock entries within the (inclusive) date window per place
+    /// purchased from, highest spend first.
+    #[oai(path = "/reports/spend_by_supplier", method = "get")]
+    async fn get_spend_by_supplier_report(
         &self,
         pool: Data<&PgPool>,
-        id: Path<i32>,
-    ) -> Result<GetResponse<StockItem>> {
-        let result: Option<StockItem> =
-            sqlx::query_as!(StockItem, "SELECT * FROM stock_items WHERE id = $1", id.0)
-                .fetch_optional(pool.0)
-                .await
-                .map_err(InternalServerError)?;
-
-        match result {
-            Some(item) => Ok(GetResponse::Success(Json(item))),
-            None => Ok(GetResponse::NotFound(PlainText(
-                format!("No stock item with id '{}' found.", id.0),
-            ))),
+        from: Query<Option<NaiveDate>>,
+        to: Query<Option<NaiveDate>>,
+    ) -> Result<ReportResponse<Vec<SupplierSpend>>> {
+        if let (Some(from), Some(to)) = (from.0, to.0) {
+            if from > to {
+                return Ok(ReportResponse::BadRequest(PlainText(format!(
+                    "The start of the period '{from}' is after its end '{to}'."
+                ))));
+            }
         }
+
+        let suppliers = sqlx::query_as!(
+            SupplierSpend,
+            r#"
+SELECT e.place_id, pl.name AS "name?",
+    COUNT(*) AS "purchase_count!",
+    COUNT(*) FILTER (WHERE e.price IS NULL) AS "unpriced_purchase_count!",
+    COALESCE(SUM(e.stock_quantity::numeric * e.price::numeric), 0)::float8 AS "total_spend!"
+FROM stock_entries e
+LEFT JOIN places pl ON pl.id = e.place_id
+WHERE e.entry_type = 'purchase' AND e.status = 'confirmed'
+  AND ($1::date IS NULL OR e.entry_timestamp >= $1::date)
+  AND ($2::date IS NULL OR e.entry_timestamp < $2::date + 1)
+GROUP BY e.place_id, pl.name
+ORDER BY 5 DESC, e.place_id"#,
+            from.0,
+            to.0
+        )
+        .fetch_all(pool.0)
+        .await
+        .map_err(InternalServerError)?;
+
+        Ok(ReportResponse::Success(Json(suppliers)))
     }
 
-    /// Stock Items: Create new
-    #[oai(path = "/stock_item", method = "post")]
-    async fn new_stock_item(
+    /// Reports: Freshness distribution
+    ///
+    /// Counts the stock items in hand by the number of days until their best by date, listing
+    /// every bucket from expired to undated, empty or not.
+    #[oai(path = "/reports/freshness_distribution", method = "get")]
+    async fn get_freshness_distribution_report(
         &self,
         pool: Data<&PgPool>,
-        item: Json<StockItem>,
-    ) -> Result<Json<i32>> {
-        let record = sqlx::query!(
+    ) -> Result<GetAllResponse<FreshnessCount>> {
+        let records = sqlx::query!(
             r#"
-INSERT INTO stock_items (product_id, space_id, stock_quantity)
-VALUES ($1, $2, $3)
-RETURNING id"#,
-            item.product_id,
-            item.space_id,
-            item.stock_quantity,
+SELECT
+    CASE
+        WHEN best_by_date IS NULL THEN 5
+        WHEN best_by_date < CURRENT_DATE THEN 0
+        WHEN best_by_date <= CURRENT_DATE + 3 THEN 1
+        WHEN best_by_date <= CURRENT_DATE + 7 THEN 2
+        WHEN best_by_date <= CURRENT_DATE + 30 THEN 3
+        ELSE 4
+    END AS "bucket!",
+    COUNT(*) AS "stock_item_count!",
+    SUM(stock_quantity)::float8 AS "total_quantity!"
+FROM stock_items
+WHERE stock_quantity > 0
+GROUP BY 1"#
         )
-        .fetch_one(pool.0)
+        .fetch_all(pool.0)
         .await
         .map_err(InternalServerError)?;
 
-        Ok(Json(record.id))
+        let mut counts = [
+            FreshnessBucket::Expired,
+            FreshnessBucket::Days0To3,
+            FreshnessBucket::Days4To7,
+            FreshnessBucket::Days8To30,
+            FreshnessBucket::Over30,
+            FreshnessBucket::Undated,
+        ]
+        .map(|bucket| FreshnessCount {
+            bucket,
+            stock_item_count: 0,
+            total_quantity: 0.0,
+        });
+        for record in records {
+            let count = &mut counts[record.bucket as usize];
+            count.stock_item_count = record.stock_item_count;
+            count.total_quantity = record.total_quantity;
+        }
+
+        Ok(Json(counts.into()))
     }
 
-    /// Stock Items: Delete with id
-    #[oai(path = "/stock_item/:id", method = "delete")]
-    async fn delete_stock_item(
+    /// Reports: Stock aging
+    ///
+    /// Buckets the stock in hand by how close it is to its best by date, overall or per product.
+    /// Quantities can be converted to a single unit to compare them across products, in which
+    /// case the stock of products whose stock unit doesn't convert to it is kept in its own
+    /// groups, in its stock unit.
+    #[oai(path = "/reports/aging", method = "get")]
+    async fn get_aging_report(
         &self,
         pool: Data<&PgPool>,
-        id: Path<i32>,
-    ) -> Result<DeleteResponse> {
-        let result = sqlx::query!(
+        group_by: Query<Option<AgingGrouping>>,
+        /// The `Unit` id of the unit to express quantities in
+        unit_id: Query<Option<i32>>,
+    ) -> Result<GetResponse<Vec<AgingGroup>>> {
+        let decimal_places: HashMap<i32, Option<i16>> =
+            sqlx::query!("SELECT id, decimal_places FROM units")
+                .fetch_all(pool.0)
+                .await
+                .map_err(InternalServerError)?
+                .into_iter()
+                .map(|unit| (unit.id, unit.decimal_places))
+                .collect();
+        if let Some(unit_id) = unit_id.0.filter(|id| !decimal_places.contains_key(id)) {
+            return Ok(GetResponse::NotFound(PlainText(format!(
+                "No unit with id '{unit_id}' found."
+            ))));
+        }
+        let by_product = matches!(group_by.0, Some(AgingGrouping::Product));
+        let records = sqlx::query!(
             r#"
-DELETE FROM stock_items
-WHERE id = $1
-RETURNING id"#,
-            id.0
+WITH aging AS (
+    SELECT si.product_id, si.stock_quantity,
+        CASE
+            WHEN si.best_by_date IS NULL THEN 4
+            WHEN si.best_by_date < CURRENT_DATE THEN 0
+            WHEN si.best_by_date < CURRENT_DATE + 7 THEN 1
+            WHEN si.best_by_date < CURRENT_DATE + 30 THEN 2
+            ELSE 3
+        END AS bucket
+    FROM stock_items si
+    WHERE si.stock_quantity > 0
+)
+SELECT a.product_id, p.name, p.stock_unit_id, a.bucket AS "bucket!",
+    COUNT(*) AS "stock_item_count!",
+    SUM(a.stock_quantity)::float8 AS "total_quantity!"
+FROM aging a
+JOIN products p ON p.id = a.product_id
+GROUP BY a.product_id, p.name, p.stock_unit_id, a.bucket
+ORDER BY a.product_id, a.bucket"#
         )
-        .fetch_optional(pool.0)
+        .fetch_all(pool.0)
         .await
         .map_err(InternalServerError)?;
+        let graph = match unit_id.0 {
+            Some(_) => Some(
+                ConversionGraph::load(pool.0, self.max_conversion_path_length)
+                    .await
+                    .map_err(InternalServerError)?,
+            ),
+            None => None,
+        };
 
-        match result {
-            Some(_) => Ok(DeleteResponse::Success(Json(id.0))),
-            None => Ok(DeleteResponse::NotFound(PlainText(
-                format!("No stock item with id '{}' found.", id.0),
-            ))),
+        // Keyed by product (when grouped by it), bucket, unconverted and unit, in report order
+        let mut groups: BTreeMap<(Option<i32>, i32, bool, Option<i32>), AgingGroup> =
+            BTreeMap::new();
+        for record in records {
+            let factor = match (&graph, unit_id.0, record.stock_unit_id) {
+                (Some(graph), Some(unit_id), Some(stock_unit_id)) => {
+                    graph.factor(stock_unit_id, unit_id).ok()
+                }
+                _ => None,
+            };
+            let (total_quantity, unit_id, converted) = match factor {
+                Some(factor) => (record.total_quantity * factor, unit_id.0, true),
+                None if by_product || unit_id.0.is_some() => {
+                    (record.total_quantity, record.stock_unit_id, false)
+                }
+                None => (record.total_quantity, None, false),
+            };
+            let product_id = by_product.then_some(record.product_id);
+
+            let group = groups
+                .entry((product_id, record.bucket, !converted, unit_id))
+                .or_insert_with(|| AgingGroup {
+                    product_id,
+                    name: by_product.then(|| record.name.clone()),
+                    bucket: match record.bucket {
+                        0 => AgingBucket::Expired,
+                        1 => AgingBucket::ExpiringThisWeek,
+                        2 => AgingBucket::ExpiringThisMonth,
+                        3 => AgingBucket::Fresh,
+                        _ => AgingBucket::Undated,
+                    },
+                    stock_item_count: 0,
+                    total_quantity: 0.0,
+                    unit_id,
+                    converted,
+                });
+            group.stock_item_count += record.stock_item_count;
+            group.total_quantity += total_quantity;
         }
+
+        Ok(GetResponse::Success(Json(
+            groups
+                .into_values()
+                .map(|group| AgingGroup {
+                    total_quantity: round_to_unit(
+                        group.total_quantity,
+                        group
+                            .unit_id
+                            .and_then(|unit_id| decimal_places.get(&unit_id).copied().flatten()),
+                    ),
+                    ..group
+                })
+                .collect(),
+        )))
     }
 }
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+
     let args: Vec<String> = env::args().collect();
     let port: u32 = if args.len() > 1 {
         args[1].parse().unwrap()
@@ -649,14 +9093,170 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     };
     let db_url = env::var("DATABASE_URL")
         .expect("env variable `DATABASE_URL` should be set prior to execution");
+    let read_only = env_flag("READ_ONLY");
+    let envelope = env_flag("RESPONSE_ENVELOPE");
+    let cors = env::var("CORS_ALLOW_ORIGINS")
+        .ok()
+        .map(|origins| cors(&origins));
+    let open_food_facts = OpenFoodFacts::new(
+        env::var("OPENFOODFACTS_URL")
+            .unwrap_or_else(|_| "https://world.openfoodfacts.org".to_string()),
+        Duration::from_secs(
+            env::var("OPENFOODFACTS_TIMEOUT")
+                .map(|timeout| {
+                    timeout.parse().expect(
+                        "env variable `OPENFOODFACTS_TIMEOUT` should be a number of seconds",
+                    )
+                })
+                .unwrap_or(10),
+        ),
+    )?;
+    let max_conversion_path_length = env::var("MAX_CONVERSION_PATH_LENGTH")
+        .map(|length| {
+            length
+                .parse()
+                .expect("env variable `MAX_CONVERSION_PATH_LENGTH` should be a number")
+        })
+        .unwrap_or(5);
+    let max_memo_length = env::var("MAX_MEMO_LENGTH")
+        .map(|length| {
+            length
+                .parse()
+                .expect("env variable `MAX_MEMO_LENGTH` should be a number")
+        })
+        .unwrap_or(1000);
+    let snapshot_interval = env::var("SNAPSHOT_INTERVAL").ok().map(|interval| {
+        let seconds = interval
+            .parse()
+            .expect("env variable `SNAPSHOT_INTERVAL` should be a number of seconds");
+        assert!(
+            seconds > 0,
+            "env variable `SNAPSHOT_INTERVAL` should be at least 1 second"
+        );
+        Duration::from_secs(seconds)
+    });
+    let auto_expire_interval = Duration::from_secs(
+        env::var("AUTO_EXPIRE_INTERVAL")
+            .map(|interval| {
+                interval
+                    .parse()
+                    .expect("env variable `AUTO_EXPIRE_INTERVAL` should be a number of seconds")
+            })
+            .unwrap_or(3600),
+    );
+    assert!(
+        !auto_expire_interval.is_zero(),
+        "env variable `AUTO_EXPIRE_INTERVAL` should be at least 1 second"
+    );
     let pool = PgPool::connect(&db_url).await?;
-    let api_service = OpenApiService::new(UkisApi, "Unnamed Kitchen Inventory System API", "0.0.1")
-        .server(format!("http://localhost:{port}"));
+    let replica = match env::var("REPLICA_DATABASE_URL") {
+        Ok(replica_url) => Some(PgPool::connect(&replica_url).await?),
+        Err(_) => None,
+    };
+    if let Some(period) = snapshot_interval.filter(|_| !read_only) {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick completes immediately, don't snapshot on every restart
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(err) = take_inventory_snapshot(&pool, false).await {
+                    tracing::error!(%err, "failed to take scheduled inventory snapshot");
+                }
+            }
+        });
+    }
+    let stock_events = broadcast::channel(256).0;
+    let stock_totals = StockTotalsRefresher::spawn(pool.clone());
+    if env_flag("AUTO_EXPIRE") && !read_only {
+        let pool = pool.clone();
+        let stock_events = stock_events.clone();
+        let stock_totals = stock_totals.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(auto_expire_interval);
+            loop {
+                interval.tick().await;
+                let changes = match expire_stock(&pool).await {
+                    Ok(changes) => changes,
+                    Err(err) => {
+                        tracing::error!(%err, "failed to expire stock");
+                        continue;
+                    }
+                };
+                tracing::info!(
+                    stock_items = changes.len(),
+                    "expired stock past its best by date"
+                );
+                if changes.is_empty() {
+                    continue;
+                }
+                stock_totals.request();
+                for change in changes {
+                    // Only fails when nobody is subscribed
+                    let _ = stock_events.send(change);
+                }
+            }
+        });
+    }
+    let api_service = OpenApiService::new(
+        UkisApi {
+            max_conversion_path_length,
+            max_memo_length,
+            allow_stock_rebuild: env_flag("ALLOW_STOCK_REBUILD"),
+            stock_events,
+            stock_totals,
+            turnover_cache: ReportCache::new(env_cache_ttl("TURNOVER_CACHE_TTL")),
+            stock_by_value_cache: ReportCache::new(env_cache_ttl("STOCK_BY_VALUE_CACHE_TTL")),
+        },
+        "Unnamed Kitchen Inventory System API",
+        "0.0.1",
+    )
+    .server(format!("http://localhost:{port}"));
     let ui = api_service.openapi_explorer();
+    let spec: serde_json::Value = serde_json::from_str(&api_service.spec())?;
+    let static_segments = spec["paths"]
+        .as_object()
+        .into_iter()
+        .flat_map(|paths| paths.keys())
+        .flat_map(|path| path.split('/'))
+        .filter(|segment| !segment.is_empty() && !segment.starts_with('{'))
+        .map(str::to_string)
+        .collect();
+    let reading_paths = spec["paths"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(_, operations)| {
+            operations["post"]["tags"]
+                .as_array()
+                .is_some_and(|tags| tags.iter().any(|tag| tag == ApiTags::Reading.name()))
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
     let route = Route::new()
-        .nest("/", api_service)
+        .nest(
+            "/",
+            api_service
+                .into_endpoint()
+                .with_if(
+                    read_only,
+                    ReadOnly {
+                        reading_paths: Arc::new(reading_paths),
+                    },
+                )
+                .with(InvalidPathIds {
+                    static_segments: Arc::new(static_segments),
+                })
+                .with(Envelope { default: envelope })
+                .with(ReadReplica { pool: replica }),
+        )
         .nest("/ui", ui)
-        .data(pool);
+        .at("/version", get(version))
+        .at("/health", get(health))
+        .with_if(cors.is_some(), cors.unwrap_or_default())
+        .data(pool)
+        .data(open_food_facts);
 
     Server::new(TcpListener::bind(format!("localhost:{port}")))
         .run(route)
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// A client for looking up products by barcode in the OpenFoodFacts database
+#[derive(Clone)]
+pub struct OpenFoodFacts {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+/// The details of a product found in OpenFoodFacts
+pub struct FoodProduct {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// Why a product couldn't be looked up
+pub enum LookupError {
+    /// OpenFoodFacts doesn't know the barcode
+    NotFound,
+    /// OpenFoodFacts didn't respond in time
+    Timeout,
+    /// The request failed, or OpenFoodFacts responded with something unexpected
+    Upstream(String),
+}
+
+#[derive(Deserialize)]
+struct ProductResponse {
+    status: i32,
+    product: Option<ProductFields>,
+}
+
+#[derive(Deserialize)]
+struct ProductFields {
+    product_name: Option<String>,
+    generic_name: Option<String>,
+    brands: Option<String>,
+    quantity: Option<String>,
+}
+
+impl OpenFoodFacts {
+    pub fn new(base_url: String, timeout: Duration) -> reqwest::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("ukis/", env!("CARGO_PKG_VERSION")))
+            .timeout(timeout)
+            .build()?;
+
+        Ok(Self { client, base_url })
+    }
+
+    /// Looks up the product with barcode `code`
+    pub async fn product(&self, code: &str) -> Result<FoodProduct, LookupError> {
+        let response = self
+            .client
+            .get(format!("{}/api/v2/product/{code}.json", self.base_url))
+            .query(&[("fields", "product_name,generic_name,brands,quantity")])
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(LookupError::NotFound);
+        }
+        let response: ProductResponse = response.error_for_status()?.json().await?;
+
+        let Some(product) = response.product.filter(|_| response.status == 1) else {
+            return Err(LookupError::NotFound);
+        };
+        let name = [&product.product_name, &product.generic_name]
+            .into_iter()
+            .flatten()
+            .map(|name| name.trim())
+            .find(|name| !name.is_empty())
+            .ok_or_else(|| LookupError::Upstream(format!("Product '{code}' has no name.")))?;
+        let description = [
+            product.generic_name.as_deref(),
+            product.brands.as_deref(),
+            product.quantity.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .filter(|part| !part.is_empty() && *part != name)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+        Ok(FoodProduct {
+            name: name.to_string(),
+            description: (!description.is_empty()).then_some(description),
+        })
+    }
+}
+
+impl From<reqwest::Error> for LookupError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            LookupError::Timeout
+        } else {
+            LookupError::Upstream(err.to_string())
+        }
+    }
+}